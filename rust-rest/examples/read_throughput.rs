@@ -0,0 +1,132 @@
+//Measures how read throughput scales with the number of concurrent clients.
+//Start the server first, with request logging turned down so it does not dominate:
+//  LOG_LEVEL=warn cargo run --release
+//then in another terminal:
+//  cargo run --release --example read_throughput
+use clap::Parser;
+use hyper::{client::HttpConnector, Client, StatusCode, Uri};
+use std::time::{Duration, Instant};
+
+#[derive(Parser, Debug)]
+#[command(about = "Read throughput of a running rust-rest server by number of concurrent clients")]
+struct Args {
+    /// Server to load
+    #[arg(long, default_value = "http://127.0.0.1:3030")]
+    url: String,
+    /// Path to request, may be repeated. Each one is measured separately.
+    /// Defaults to a page of questions, the tags and the first question
+    #[arg(long = "path")]
+    paths: Vec<String>,
+    /// Comma separated numbers of concurrent clients to measure
+    #[arg(long, value_delimiter = ',', default_value = "1,2,4,8,16,32")]
+    clients: Vec<usize>,
+    /// Seconds to measure each number of clients for
+    #[arg(long, default_value_t = 5)]
+    seconds: u64,
+}
+
+//Requests that succeeded and failed while the client ran
+#[derive(Default)]
+struct Counts {
+    ok: u64,
+    failed: u64,
+}
+
+//Default paths: lists read from the database and a single question served from the cache.
+//Question ids are generated, so the first one is looked up on the server
+async fn default_paths(http: &Client<HttpConnector>, url: &str) -> Vec<String> {
+    let mut paths = vec![
+        "/api/v1/questions?limit=20".to_string(),
+        "/api/v1/tags".to_string(),
+    ];
+    let uri: Uri = format!("{url}/api/v1/questions?limit=1")
+        .parse()
+        .expect("--url must form a valid URI");
+    let response = http.get(uri).await.expect("the server must be running");
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .expect("failed to read the list of questions");
+    let page: serde_json::Value =
+        serde_json::from_slice(&body).expect("the list of questions must be JSON");
+    match page["items"][0]["id"].as_str() {
+        Some(id) => paths.push(format!("/api/v1/questions/{id}")),
+        None => eprintln!("No questions stored, skipping /api/v1/questions/<id>"),
+    }
+    paths
+}
+
+//Sends one request after the other until the deadline
+async fn client(http: Client<HttpConnector>, uri: Uri, deadline: Instant) -> Counts {
+    let mut counts = Counts::default();
+    while Instant::now() < deadline {
+        let ok = match http.get(uri.clone()).await {
+            //Read the whole body so the connection can be reused
+            Ok(response) => {
+                let status = response.status();
+                hyper::body::to_bytes(response.into_body()).await.is_ok()
+                    && status == StatusCode::OK
+            }
+            Err(_) => false,
+        };
+        if ok {
+            counts.ok += 1;
+        } else {
+            counts.failed += 1;
+        }
+    }
+    counts
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let max_clients = args.clients.iter().copied().max().unwrap_or(1);
+    let http = Client::builder()
+        .pool_max_idle_per_host(max_clients)
+        .build_http();
+    let duration = Duration::from_secs(args.seconds);
+    let paths = if args.paths.is_empty() {
+        default_paths(&http, &args.url).await
+    } else {
+        args.paths
+    };
+
+    println!(
+        "{:<44} {:>8} {:>12} {:>8} {:>8}",
+        "path", "clients", "requests/s", "scaling", "failed"
+    );
+    for path in &paths {
+        let uri: Uri = format!("{}{}", args.url, path)
+            .parse()
+            .expect("--url and --path must form a valid URI");
+        //Warm up the connection pool and the server
+        client(
+            http.clone(),
+            uri.clone(),
+            Instant::now() + Duration::from_millis(500),
+        )
+        .await;
+
+        let mut single = None;
+        for &clients in &args.clients {
+            let deadline = Instant::now() + duration;
+            let tasks: Vec<_> = (0..clients)
+                .map(|_| tokio::spawn(client(http.clone(), uri.clone(), deadline)))
+                .collect();
+            let mut total = Counts::default();
+            for task in tasks {
+                let counts = task.await.expect("client task panicked");
+                total.ok += counts.ok;
+                total.failed += counts.failed;
+            }
+
+            let per_second = total.ok as f64 / duration.as_secs_f64();
+            //Relative to the first number of clients measured, normally 1
+            let scaling = per_second / *single.get_or_insert(per_second);
+            println!(
+                "{:<44} {:>8} {:>12.0} {:>7.1}x {:>8}",
+                path, clients, per_second, scaling, total.failed
+            );
+        }
+    }
+}
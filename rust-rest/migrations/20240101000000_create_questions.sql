@@ -0,0 +1,8 @@
+-- IF NOT EXISTS keeps databases set up by hand from the old README working
+CREATE TABLE IF NOT EXISTS questions (
+  id TEXT PRIMARY KEY,
+  title TEXT NOT NULL,
+  content TEXT NOT NULL,
+  tags TEXT [],
+  created_on TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS answers (
+  id TEXT PRIMARY KEY,
+  content TEXT NOT NULL,
+  question_id TEXT NOT NULL REFERENCES questions(id) ON DELETE CASCADE,
+  created_on TIMESTAMP NOT NULL DEFAULT NOW()
+);
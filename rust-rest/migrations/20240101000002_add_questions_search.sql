@@ -0,0 +1,16 @@
+-- Generated columns need an IMMUTABLE expression and array_to_string is only STABLE,
+-- so the document is built in a function we declare immutable
+CREATE FUNCTION question_search_document(title TEXT, content TEXT, tags TEXT[])
+RETURNS tsvector
+LANGUAGE sql IMMUTABLE
+AS $$
+  SELECT setweight(to_tsvector('english', title), 'A')
+      || setweight(to_tsvector('english', content), 'B')
+      || setweight(to_tsvector('english', coalesce(array_to_string(tags, ' '), '')), 'C')
+$$;
+
+ALTER TABLE questions
+  ADD COLUMN search_document tsvector
+  GENERATED ALWAYS AS (question_search_document(title, content, tags)) STORED;
+
+CREATE INDEX questions_search_document_idx ON questions USING GIN (search_document);
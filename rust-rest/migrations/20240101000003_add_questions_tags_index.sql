@@ -0,0 +1,2 @@
+-- Serves the && (any) and @> (all) tag filters
+CREATE INDEX questions_tags_idx ON questions USING GIN (tags);
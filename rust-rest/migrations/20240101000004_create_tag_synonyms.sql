@@ -0,0 +1,18 @@
+-- Questions tagged with a synonym are stored under the canonical tag
+CREATE TABLE IF NOT EXISTS tag_synonyms (
+  synonym TEXT PRIMARY KEY,
+  canonical TEXT NOT NULL,
+  created_on TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- Tags used to be stored as typed, clean up the " rust" / "Rust" variants
+UPDATE questions SET tags = ARRAY(
+  SELECT tag FROM (
+    SELECT lower(btrim(tag)) AS tag, MIN(ord) AS first
+    FROM unnest(questions.tags) WITH ORDINALITY AS t(tag, ord)
+    WHERE btrim(tag) <> ''
+    GROUP BY 1
+  ) normalized
+  ORDER BY first
+)
+WHERE tags IS NOT NULL;
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS users (
+  id TEXT PRIMARY KEY,
+  username TEXT NOT NULL UNIQUE,
+  password_hash TEXT NOT NULL,
+  created_on TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- Rows from before accounts existed have no author
+ALTER TABLE questions ADD COLUMN author_id TEXT REFERENCES users(id) ON DELETE SET NULL;
+ALTER TABLE answers ADD COLUMN author_id TEXT REFERENCES users(id) ON DELETE SET NULL;
@@ -0,0 +1,23 @@
+-- Every instance LISTENs on cache_changes and reloads the rows named in the payload,
+-- so edits made by other instances or by hand with psql reach every cache.
+-- The payload only carries the id, NOTIFY payloads are limited to 8000 bytes
+CREATE OR REPLACE FUNCTION notify_cache_change() RETURNS trigger AS $$
+BEGIN
+  IF TG_OP <> 'INSERT' THEN
+    PERFORM pg_notify('cache_changes', json_build_object('table', TG_TABLE_NAME, 'id', OLD.id)::text);
+  END IF;
+  -- An update that changes the id is a delete of the old id and an insert of the new one
+  IF TG_OP = 'INSERT' OR (TG_OP = 'UPDATE' AND NEW.id IS DISTINCT FROM OLD.id) THEN
+    PERFORM pg_notify('cache_changes', json_build_object('table', TG_TABLE_NAME, 'id', NEW.id)::text);
+  END IF;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER questions_notify_cache_change
+  AFTER INSERT OR UPDATE OR DELETE ON questions
+  FOR EACH ROW EXECUTE FUNCTION notify_cache_change();
+
+CREATE TRIGGER answers_notify_cache_change
+  AFTER INSERT OR UPDATE OR DELETE ON answers
+  FOR EACH ROW EXECUTE FUNCTION notify_cache_change();
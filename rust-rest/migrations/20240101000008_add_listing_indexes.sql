@@ -0,0 +1,4 @@
+-- Questions are listed a page at a time straight from the table, ordered by (created_on, id)
+-- and continuing after the cursor, and answers are read one question at a time
+CREATE INDEX questions_created_on_id_idx ON questions (created_on, id);
+CREATE INDEX answers_question_id_idx ON answers (question_id, id);
@@ -0,0 +1,17 @@
+-- Answers are cached per question, so their notifications also name the question.
+-- question_id is null for the questions table
+CREATE OR REPLACE FUNCTION notify_cache_change() RETURNS trigger AS $$
+BEGIN
+  IF TG_OP <> 'INSERT' THEN
+    PERFORM pg_notify('cache_changes', json_build_object(
+      'table', TG_TABLE_NAME, 'id', OLD.id, 'question_id', to_jsonb(OLD)->>'question_id')::text);
+  END IF;
+  -- An update that changes the id, or moves an answer to another question, touches two cache entries
+  IF TG_OP = 'INSERT' OR (TG_OP = 'UPDATE' AND (NEW.id IS DISTINCT FROM OLD.id
+      OR to_jsonb(NEW)->>'question_id' IS DISTINCT FROM to_jsonb(OLD)->>'question_id')) THEN
+    PERFORM pg_notify('cache_changes', json_build_object(
+      'table', TG_TABLE_NAME, 'id', NEW.id, 'question_id', to_jsonb(NEW)->>'question_id')::text);
+  END IF;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,15 @@
+-- Clients send the version they edited back in If-Match, a write against any other
+-- version is refused. The trigger bumps it on every update, including tag merges
+-- and edits made by hand with psql, so none of them can be overwritten unseen
+ALTER TABLE questions ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+
+CREATE FUNCTION bump_question_version() RETURNS trigger AS $$
+BEGIN
+  NEW.version := OLD.version + 1;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER questions_bump_version
+  BEFORE UPDATE ON questions
+  FOR EACH ROW EXECUTE FUNCTION bump_question_version();
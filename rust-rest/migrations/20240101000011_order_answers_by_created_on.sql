@@ -0,0 +1,4 @@
+-- Answers are listed by (created_on, id): ids can be chosen by clients, so they do not
+-- follow creation order. Replaces the (question_id, id) index for that ordering
+CREATE INDEX answers_question_id_created_on_id_idx ON answers (question_id, created_on, id);
+DROP INDEX answers_question_id_idx;
@@ -0,0 +1,12 @@
+-- Every instance keeps the synonyms in memory and reloads all of them on a change, so
+-- one notification per statement is enough. It names no row, so the payload has no id
+CREATE FUNCTION notify_tag_synonyms_change() RETURNS trigger AS $$
+BEGIN
+  PERFORM pg_notify('cache_changes', json_build_object('table', TG_TABLE_NAME)::text);
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER tag_synonyms_notify_cache_change
+  AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON tag_synonyms
+  FOR EACH STATEMENT EXECUTE FUNCTION notify_tag_synonyms_change();
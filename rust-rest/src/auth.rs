@@ -0,0 +1,365 @@
+use crate::{
+    config::{AuthConfig, Config},
+    error::Error,
+    extract::JsonBody,
+    generate_id, metrics, now, policy, Store,
+};
+use argon2::{
+    password_hash::{rand_core::OsRng, rand_core::RngCore, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Path, State},
+    http::{header, request::Parts, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{NaiveDateTime, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+//How long a token from /auth/login stays valid
+const TOKEN_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+//What a user is allowed to do, see policy.rs
+#[derive(
+    Serialize, Deserialize, sqlx::Type, ToSchema, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Moderator,
+    Admin,
+}
+
+//Serialize is for the file backend, clients get a UserProfile instead
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    pub password_hash: String,
+    pub created_on: NaiveDateTime,
+    #[serde(default)]
+    pub role: Role,
+}
+
+//What clients see of a user, without the password hash
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct UserProfile {
+    pub id: String,
+    pub username: String,
+    pub created_on: NaiveDateTime,
+    pub role: Role,
+}
+
+impl From<&User> for UserProfile {
+    fn from(user: &User) -> Self {
+        UserProfile {
+            id: user.id.clone(),
+            username: user.username.clone(),
+            created_on: user.created_on,
+            role: user.role,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+//Keys for signing and checking session tokens (HS256 JWTs)
+pub struct AuthKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl AuthKeys {
+    pub fn from_secret(secret: &[u8]) -> Self {
+        AuthKeys {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    //Uses auth.jwt_secret, or a random secret so local runs work without setup.
+    //With a random secret every restart logs everyone out
+    pub fn from_config(config: &AuthConfig) -> Self {
+        match config.jwt_secret.as_deref() {
+            Some(secret) if !secret.is_empty() => Self::from_secret(secret.as_bytes()),
+            _ => {
+                tracing::warn!("JWT_SECRET is not set, using a random secret for this run");
+                let mut secret = [0u8; 32];
+                OsRng.fill_bytes(&mut secret);
+                Self::from_secret(&secret)
+            }
+        }
+    }
+}
+
+//The token only says who the user is. Their role is looked up on every request,
+//so tokens issued before a role change or account deletion carry no stale rights
+#[derive(Serialize, Deserialize, Debug)]
+struct Claims {
+    sub: String,
+    username: String,
+    iat: i64,
+    exp: i64,
+}
+
+//The signed in user, taken from the `Authorization: Bearer <token>` header.
+//Handlers that take this extractor reject anonymous requests with a 401.
+//The role is the one stored for the user now, not the one they had at login
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: String,
+    pub role: Role,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    Arc<AuthKeys>: FromRef<S>,
+    Arc<Store>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let keys = Arc::<AuthKeys>::from_ref(state);
+        let store = Arc::<Store>::from_ref(state);
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(Error::Unauthorized("Missing bearer token".to_string()))?;
+
+        let claims = jsonwebtoken::decode::<Claims>(token, &keys.decoding, &Validation::default())
+            .map_err(|_| Error::Unauthorized("Invalid or expired token".to_string()))?
+            .claims;
+
+        //Usernames are unique but may be taken again after an account is deleted,
+        //so the id has to match as well
+        let user = store
+            .repo
+            .get_user_by_username(&claims.username)
+            .await?
+            .filter(|user| user.id == claims.sub)
+            .ok_or(Error::Unauthorized(
+                "The signed in account no longer exists".to_string(),
+            ))?;
+
+        Ok(AuthUser {
+            id: user.id,
+            role: user.role,
+        })
+    }
+}
+
+fn validate_username(username: &str) -> Result<(), Error> {
+    let valid_length = (3..=32).contains(&username.len());
+    let valid_chars = username
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_'));
+    if !valid_length || !valid_chars {
+        return Err(Error::Validation(
+            "Username must be 3 to 32 lowercase letters, digits, '-' or '_'".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+//Argon2 is deliberately slow, so keep it off the async worker threads
+async fn hash_password(password: String) -> Result<String, Error> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .expect("Password hashing task panicked")
+    .map_err(|e| Error::Internal(format!("Failed to hash password: {e}")))
+}
+
+async fn verify_password(password: String, password_hash: String) -> bool {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&password_hash)
+            .map(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    })
+    .await
+    .unwrap_or(false)
+}
+
+//Handler to create an account
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/register",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 201, description = "Account created", body = UserProfile),
+        (status = 409, description = "Username is already taken", body = ErrorBody),
+        (status = 422, description = "Invalid username or password too short", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(username = %credentials.username))]
+pub async fn register(
+    State(store): State<Arc<Store>>,
+    State(config): State<Arc<Config>>,
+    JsonBody(credentials): JsonBody<Credentials>,
+) -> Result<impl IntoResponse, Error> {
+    let username = credentials.username.trim().to_lowercase();
+    validate_username(&username)?;
+    if credentials.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(Error::Validation(format!(
+            "Password must be at least {MIN_PASSWORD_LENGTH} characters"
+        )));
+    }
+
+    //auth.admin_usernames bootstraps the first admins, everyone else starts as a user
+    let is_admin = config
+        .auth
+        .admin_usernames
+        .iter()
+        .any(|admin| admin.trim().to_lowercase() == username);
+    let role = if is_admin { Role::Admin } else { Role::User };
+    let user = User {
+        id: generate_id(),
+        username,
+        password_hash: hash_password(credentials.password).await?,
+        created_on: now(),
+        role,
+    };
+
+    //Serialized with other writes so two registrations cannot both pass the check
+    let _writes = metrics::lock_writes(&store.writes).await;
+    if store
+        .repo
+        .get_user_by_username(&user.username)
+        .await?
+        .is_some()
+    {
+        return Err(Error::Conflict("Username is already taken".to_string()));
+    }
+    store.repo.insert_user(&user).await?;
+
+    Ok((StatusCode::CREATED, Json(UserProfile::from(&user))))
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct LoginResponse {
+    token: String,
+    //Always "Bearer"
+    token_type: &'static str,
+    //Seconds until the token expires
+    expires_in: i64,
+    user: UserProfile,
+}
+
+//Handler to exchange a username and password for a session token
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/login",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 200, description = "Signed in", body = LoginResponse),
+        (status = 401, description = "Invalid username or password", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(username = %credentials.username))]
+pub async fn login(
+    State(store): State<Arc<Store>>,
+    State(keys): State<Arc<AuthKeys>>,
+    JsonBody(credentials): JsonBody<Credentials>,
+) -> Result<Json<LoginResponse>, Error> {
+    let username = credentials.username.trim().to_lowercase();
+    let user = store.repo.get_user_by_username(&username).await?;
+
+    //Same error for an unknown user and a wrong password
+    let invalid = || Error::Unauthorized("Invalid username or password".to_string());
+    let user = user.ok_or_else(invalid)?;
+    if !verify_password(credentials.password, user.password_hash.clone()).await {
+        return Err(invalid());
+    }
+
+    let issued_at = Utc::now().timestamp();
+    let claims = Claims {
+        sub: user.id.clone(),
+        username: user.username.clone(),
+        iat: issued_at,
+        exp: issued_at + TOKEN_TTL_SECONDS,
+    };
+    let token = jsonwebtoken::encode(&Header::default(), &claims, &keys.encoding)
+        .map_err(|e| Error::Internal(format!("Failed to sign token: {e}")))?;
+
+    Ok(Json(LoginResponse {
+        token,
+        token_type: "Bearer",
+        expires_in: TOKEN_TTL_SECONDS,
+        user: UserProfile::from(&user),
+    }))
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct SetRole {
+    role: Role,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct RoleChange {
+    username: String,
+    role: Role,
+}
+
+//Handler to change a user's role
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/{username}/role",
+    tag = "admin",
+    params(("username" = String, Path, description = "User to change")),
+    request_body = SetRole,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Role changed", body = RoleChange),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id, username = %username))]
+pub async fn set_role(
+    State(store): State<Arc<Store>>,
+    user: AuthUser,
+    Path(username): Path<String>,
+    JsonBody(set_role): JsonBody<SetRole>,
+) -> Result<Json<RoleChange>, Error> {
+    policy::can_manage_users(&user)?;
+
+    let username = username.trim().to_lowercase();
+    //Like every other write, so the file backend never saves two changes at once
+    let _writes = metrics::lock_writes(&store.writes).await;
+    if !store.repo.set_user_role(&username, set_role.role).await? {
+        return Err(Error::UserNotFound);
+    }
+
+    Ok(Json(RoleChange {
+        username,
+        role: set_role.role,
+    }))
+}
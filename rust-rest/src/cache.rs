@@ -0,0 +1,250 @@
+use crate::metrics;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+//A bounded map that evicts the least recently used entry when it is full and
+//drops entries older than the ttl when they are looked up. Every operation takes
+//the lock for a few map operations only, values are cloned out
+pub struct Lru<K, V> {
+    //Label for the metrics, e.g. "questions"
+    name: &'static str,
+    capacity: usize,
+    ttl: Option<Duration>,
+    inner: Mutex<Inner<K, V>>,
+}
+
+struct Entry<V> {
+    value: V,
+    stored: Instant,
+    last_used: u64,
+}
+
+struct Inner<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    //last_used -> key, the first entry is the least recently used
+    recency: BTreeMap<u64, K>,
+    //Bumped on every hit and every insert, orders the entries by use
+    clock: u64,
+    //Bumped on every write or invalidation, see Lru::fill
+    generation: u64,
+    //Generation of the last write or invalidation of each key. Emptied by clear, and
+    //when it grows to the capacity, so it never holds more keys than the entries do
+    written: HashMap<K, u64>,
+    //Generation of the last clear, every key counts as written then
+    cleared: u64,
+}
+
+impl<K, V> Inner<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    fn touch(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<Entry<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.last_used);
+        Some(entry)
+    }
+
+    //Starts a new generation in which `key` was written
+    fn written(&mut self, key: &K, capacity: usize) {
+        self.generation += 1;
+        if self.written.len() >= capacity && !self.written.contains_key(key) {
+            self.forget_written();
+        }
+        self.written.insert(key.clone(), self.generation);
+    }
+
+    //Counts every key as written in the current generation
+    fn forget_written(&mut self) {
+        self.written.clear();
+        self.cleared = self.generation;
+    }
+}
+
+impl<K, V> Lru<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    //A ttl of None keeps entries until they are evicted
+    pub fn new(name: &'static str, capacity: usize, ttl: Option<Duration>) -> Self {
+        Lru {
+            name,
+            capacity,
+            ttl,
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                clock: 0,
+                generation: 0,
+                written: HashMap::new(),
+                cleared: 0,
+            }),
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        let expired = match inner.entries.get(key) {
+            None => {
+                metrics::record_cache_lookup(self.name, false);
+                return None;
+            }
+            Some(entry) => self.ttl.is_some_and(|ttl| entry.stored.elapsed() >= ttl),
+        };
+        if expired {
+            inner.remove(key);
+            metrics::record_cache_eviction(self.name, "expired");
+            metrics::record_cache_lookup(self.name, false);
+            return None;
+        }
+
+        let now = inner.touch();
+        let inner = &mut *inner;
+        let entry = inner.entries.get_mut(key)?;
+        let key = inner.recency.remove(&entry.last_used)?;
+        inner.recency.insert(now, key);
+        entry.last_used = now;
+        metrics::record_cache_lookup(self.name, true);
+        Some(entry.value.clone())
+    }
+
+    //Pass to fill when caching a value read from storage
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+
+    //Caches a value read from storage, unless `key` was written or invalidated since
+    //`generation` was taken before the read. The value may be stale by then, and the
+    //next lookup reads storage again instead. Writes to other keys do not matter
+    pub fn fill(&self, key: K, value: V, generation: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        let written = inner.written.get(&key).copied().unwrap_or(inner.cleared);
+        if written <= generation {
+            self.store(&mut inner, key, value);
+        }
+    }
+
+    //Caches a value that was just written to storage
+    pub fn insert(&self, key: K, value: V) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.written(&key, self.capacity);
+        self.store(&mut inner, key, value);
+    }
+
+    pub fn remove<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        inner.written(&key.to_owned(), self.capacity);
+        inner.remove(key);
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.forget_written();
+        inner.entries.clear();
+        inner.recency.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn store(&self, inner: &mut Inner<K, V>, key: K, value: V) {
+        inner.remove(&key);
+        while inner.entries.len() >= self.capacity {
+            let Some((_, oldest)) = inner.recency.pop_first() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+            metrics::record_cache_eviction(self.name, "capacity");
+        }
+        let last_used = inner.touch();
+        inner.recency.insert(last_used, key.clone());
+        inner.entries.insert(
+            key,
+            Entry {
+                value,
+                stored: Instant::now(),
+                last_used,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lru() -> Lru<String, u32> {
+        Lru::new("test", 2, None)
+    }
+
+    #[test]
+    fn fill_skips_a_key_written_since_the_read() {
+        let cache = lru();
+        let generation = cache.generation();
+        cache.insert("a".to_string(), 2);
+        cache.fill("a".to_string(), 1, generation);
+        assert_eq!(cache.get("a"), Some(2));
+
+        let generation = cache.generation();
+        cache.remove("a");
+        cache.fill("a".to_string(), 1, generation);
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn fill_ignores_writes_to_other_keys() {
+        let cache = lru();
+        let generation = cache.generation();
+        cache.insert("b".to_string(), 2);
+        cache.remove("c");
+        cache.fill("a".to_string(), 1, generation);
+        assert_eq!(cache.get("a"), Some(1));
+    }
+
+    #[test]
+    fn fill_skips_every_key_after_a_clear() {
+        let cache = lru();
+        let generation = cache.generation();
+        cache.clear();
+        cache.fill("a".to_string(), 1, generation);
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn forgetting_old_writes_still_skips_reads_from_before() {
+        let cache = lru();
+        let generation = cache.generation();
+        cache.insert("a".to_string(), 2);
+        //More keys written than the cache holds, so the write to "a" is forgotten
+        cache.remove("b");
+        cache.remove("c");
+        cache.fill("a".to_string(), 1, generation);
+        assert_eq!(cache.get("a"), Some(2));
+    }
+}
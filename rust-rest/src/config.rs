@@ -0,0 +1,493 @@
+use clap::{builder::BoolishValueParser, Parser, ValueEnum};
+use http::HeaderValue;
+use serde::Deserialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+//Read when neither --config nor CONFIG_FILE is given. A missing default file is not an error
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+//Settings are layered: built in defaults, then the TOML file, then environment
+//variables, then command line flags. Later layers win
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen_addr: SocketAddr,
+    //Origins allowed by CORS, "*" allows any origin
+    pub cors_origins: Vec<String>,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+    //How long in-flight requests get to finish after SIGTERM or Ctrl-C
+    pub shutdown_timeout_secs: u64,
+    //How long /readyz reports 503 before new connections are refused, so load
+    //balancers notice and stop sending traffic first. 0 refuses them right away
+    pub shutdown_readiness_grace_secs: u64,
+    //How long each /readyz check may take before it counts as failed
+    pub readiness_timeout_ms: u64,
+    //Reject PUT, PATCH and DELETE of a question without If-Match with 428.
+    //Off by default so clients that never read ETags keep working
+    pub require_if_match: bool,
+    pub storage: StorageConfig,
+    pub database: DatabaseConfig,
+    pub cache: CacheConfig,
+    pub auth: AuthConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen_addr: SocketAddr::from(([127, 0, 0, 1], 3030)),
+            cors_origins: vec!["http://127.0.0.1:9090".to_string()],
+            log_level: LogLevel::Info,
+            log_format: LogFormat::Text,
+            shutdown_timeout_secs: 30,
+            shutdown_readiness_grace_secs: 5,
+            readiness_timeout_ms: 1000,
+            require_if_match: false,
+            storage: StorageConfig::default(),
+            database: DatabaseConfig::default(),
+            cache: CacheConfig::default(),
+            auth: AuthConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    //One JSON object per line, for log collectors
+    Json,
+}
+
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Postgres,
+    Memory,
+    File,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: Backend,
+    //Only used by the file backend
+    pub questions_file: PathBuf,
+    pub answers_file: PathBuf,
+    pub tag_synonyms_file: PathBuf,
+    pub users_file: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: Backend::Postgres,
+            questions_file: PathBuf::from("questions.json"),
+            answers_file: PathBuf::from("answers.json"),
+            tag_synonyms_file: PathBuf::from("tag_synonyms.json"),
+            users_file: PathBuf::from("users.json"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    //Required for the postgres backend
+    pub url: Option<String>,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    //How long a request waits for a free connection before failing with a 503
+    pub acquire_timeout_secs: u64,
+    //Statements slower than this are logged as warnings, faster ones at debug
+    pub slow_statement_ms: u64,
+    //Idle connections above min_connections are closed after this long, 0 keeps them open
+    pub idle_timeout_secs: u64,
+    pub run_migrations: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: None,
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_secs: 30,
+            slow_statement_ms: 500,
+            idle_timeout_secs: 600,
+            run_migrations: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    //Most questions kept in memory, and separately the most questions whose answers are
+    pub capacity: usize,
+    //Cached entries older than this are read again from storage, 0 keeps them until evicted
+    pub ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            capacity: 10_000,
+            ttl_secs: 300,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    //Secret for signing tokens. A random one is used when it is not set
+    pub jwt_secret: Option<String>,
+    //These usernames become admins when they register
+    pub admin_usernames: Vec<String>,
+}
+
+//Command line flags. Each one can also be set through the environment variable named next to it.
+//The /// comments are the --help text
+#[derive(Parser, Debug)]
+#[command(version, about = "Questions and answers REST API")]
+pub struct Cli {
+    /// TOML config file [default: config.toml when it exists]
+    #[arg(long, env = "CONFIG_FILE")]
+    config: Option<PathBuf>,
+    /// Apply migrations and exit without serving traffic
+    #[arg(long)]
+    pub migrate_only: bool,
+    /// Address and port to listen on [default: 127.0.0.1:3030]
+    #[arg(long, env = "LISTEN_ADDR")]
+    listen_addr: Option<SocketAddr>,
+    /// Comma separated origins allowed by CORS, "*" allows any
+    #[arg(long, env = "CORS_ORIGINS", value_delimiter = ',')]
+    cors_origins: Option<Vec<String>>,
+    /// [default: info]
+    #[arg(long, env = "LOG_LEVEL")]
+    log_level: Option<LogLevel>,
+    /// [default: text]
+    #[arg(long, env = "LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+    /// Seconds in-flight requests get to finish on shutdown [default: 30]
+    #[arg(long, env = "SHUTDOWN_TIMEOUT_SECS")]
+    shutdown_timeout_secs: Option<u64>,
+    /// Seconds /readyz reports 503 before connections are refused on shutdown [default: 5]
+    #[arg(long, env = "SHUTDOWN_READINESS_GRACE_SECS")]
+    shutdown_readiness_grace_secs: Option<u64>,
+    /// Milliseconds each readiness check may take [default: 1000]
+    #[arg(long, env = "READINESS_TIMEOUT_MS")]
+    readiness_timeout_ms: Option<u64>,
+    /// Require If-Match on question PUT, PATCH and DELETE [default: false]
+    #[arg(long, env = "REQUIRE_IF_MATCH", value_parser = BoolishValueParser::new())]
+    require_if_match: Option<bool>,
+    /// [default: postgres]
+    #[arg(long, env = "STORAGE_BACKEND")]
+    storage_backend: Option<Backend>,
+    /// Questions file for the file backend
+    #[arg(long, env = "QUESTIONS_FILE")]
+    questions_file: Option<PathBuf>,
+    /// Answers file for the file backend
+    #[arg(long, env = "ANSWERS_FILE")]
+    answers_file: Option<PathBuf>,
+    /// Tag synonyms file for the file backend
+    #[arg(long, env = "TAG_SYNONYMS_FILE")]
+    tag_synonyms_file: Option<PathBuf>,
+    /// Users file for the file backend
+    #[arg(long, env = "USERS_FILE")]
+    users_file: Option<PathBuf>,
+    /// PostgreSQL connection string
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true)]
+    database_url: Option<String>,
+    /// [default: 10]
+    #[arg(long, env = "DB_MAX_CONNECTIONS")]
+    db_max_connections: Option<u32>,
+    /// [default: 0]
+    #[arg(long, env = "DB_MIN_CONNECTIONS")]
+    db_min_connections: Option<u32>,
+    /// Seconds to wait for a free connection [default: 30]
+    #[arg(long, env = "DB_ACQUIRE_TIMEOUT_SECS")]
+    db_acquire_timeout_secs: Option<u64>,
+    /// Statements slower than this many milliseconds are logged as warnings [default: 500]
+    #[arg(long, env = "DB_SLOW_STATEMENT_MS")]
+    db_slow_statement_ms: Option<u64>,
+    /// Seconds before idle connections are closed, 0 keeps them [default: 600]
+    #[arg(long, env = "DB_IDLE_TIMEOUT_SECS")]
+    db_idle_timeout_secs: Option<u64>,
+    /// Apply pending migrations on startup [default: true]
+    #[arg(long, env = "RUN_MIGRATIONS", value_parser = BoolishValueParser::new())]
+    run_migrations: Option<bool>,
+    /// Most questions to keep cached [default: 10000]
+    #[arg(long, env = "CACHE_CAPACITY")]
+    cache_capacity: Option<usize>,
+    /// Seconds before a cached entry is read again, 0 keeps it until evicted [default: 300]
+    #[arg(long, env = "CACHE_TTL_SECS")]
+    cache_ttl_secs: Option<u64>,
+    /// Secret for signing tokens, random when unset
+    #[arg(long, env = "JWT_SECRET", hide_env_values = true)]
+    jwt_secret: Option<String>,
+    /// Comma separated usernames that become admins when they register
+    #[arg(long, env = "ADMIN_USERNAMES", value_delimiter = ',')]
+    admin_usernames: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "cannot read {}: {e}", path.display()),
+            ConfigError::Parse(path, e) => write!(f, "cannot parse {}: {e}", path.display()),
+            ConfigError::Invalid(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Cli {
+    //Builds the final config from the file named by --config/CONFIG_FILE and these flags
+    pub fn load_config(&self) -> Result<Config, ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => read_file(path)?,
+            None => {
+                let path = PathBuf::from(DEFAULT_CONFIG_FILE);
+                if path.exists() {
+                    read_file(&path)?
+                } else {
+                    Config::default()
+                }
+            }
+        };
+        self.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply(&self, config: &mut Config) {
+        fn set<T: Clone>(target: &mut T, value: &Option<T>) {
+            if let Some(value) = value {
+                *target = value.clone();
+            }
+        }
+
+        set(&mut config.listen_addr, &self.listen_addr);
+        set(&mut config.cors_origins, &self.cors_origins);
+        set(&mut config.log_level, &self.log_level);
+        set(&mut config.log_format, &self.log_format);
+        set(
+            &mut config.shutdown_timeout_secs,
+            &self.shutdown_timeout_secs,
+        );
+        set(
+            &mut config.shutdown_readiness_grace_secs,
+            &self.shutdown_readiness_grace_secs,
+        );
+        set(&mut config.readiness_timeout_ms, &self.readiness_timeout_ms);
+        set(&mut config.require_if_match, &self.require_if_match);
+        set(&mut config.storage.backend, &self.storage_backend);
+        set(&mut config.storage.questions_file, &self.questions_file);
+        set(&mut config.storage.answers_file, &self.answers_file);
+        set(
+            &mut config.storage.tag_synonyms_file,
+            &self.tag_synonyms_file,
+        );
+        set(&mut config.storage.users_file, &self.users_file);
+        if self.database_url.is_some() {
+            config.database.url = self.database_url.clone();
+        }
+        set(
+            &mut config.database.max_connections,
+            &self.db_max_connections,
+        );
+        set(
+            &mut config.database.min_connections,
+            &self.db_min_connections,
+        );
+        set(
+            &mut config.database.acquire_timeout_secs,
+            &self.db_acquire_timeout_secs,
+        );
+        set(
+            &mut config.database.slow_statement_ms,
+            &self.db_slow_statement_ms,
+        );
+        set(
+            &mut config.database.idle_timeout_secs,
+            &self.db_idle_timeout_secs,
+        );
+        set(&mut config.database.run_migrations, &self.run_migrations);
+        set(&mut config.cache.capacity, &self.cache_capacity);
+        set(&mut config.cache.ttl_secs, &self.cache_ttl_secs);
+        if self.jwt_secret.is_some() {
+            config.auth.jwt_secret = self.jwt_secret.clone();
+        }
+        set(&mut config.auth.admin_usernames, &self.admin_usernames);
+    }
+}
+
+fn read_file(path: &Path) -> Result<Config, ConfigError> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+    toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Invalid(message));
+
+        if self.cors_origins.is_empty() {
+            return invalid("cors_origins must list at least one origin, or \"*\"".to_string());
+        }
+        for origin in &self.cors_origins {
+            let valid = origin == "*"
+                || ((origin.starts_with("http://") || origin.starts_with("https://"))
+                    && HeaderValue::from_str(origin).is_ok());
+            if !valid {
+                return invalid(format!(
+                    "cors_origins entry {origin:?} must be \"*\" or start with http:// or https://"
+                ));
+            }
+        }
+
+        if self.readiness_timeout_ms == 0 {
+            return invalid("readiness_timeout_ms must be at least 1".to_string());
+        }
+
+        if self.storage.backend == Backend::Postgres
+            && self.database.url.as_deref().is_none_or(str::is_empty)
+        {
+            return invalid(
+                "database.url (or DATABASE_URL) must be set for the postgres backend".to_string(),
+            );
+        }
+        //One connection stays with the cache change listener
+        if self.database.max_connections < 2 {
+            return invalid("database.max_connections must be at least 2".to_string());
+        }
+        if self.database.min_connections > self.database.max_connections {
+            return invalid(format!(
+                "database.min_connections ({}) must not be above database.max_connections ({})",
+                self.database.min_connections, self.database.max_connections
+            ));
+        }
+        if self.database.acquire_timeout_secs == 0 {
+            return invalid("database.acquire_timeout_secs must be at least 1".to_string());
+        }
+
+        if self.cache.capacity == 0 {
+            return invalid("cache.capacity must be at least 1".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Valid without a database, so the environment cannot make it fail
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.storage.backend = Backend::Memory;
+        config
+    }
+
+    fn invalid(config: &Config) -> String {
+        match config.validate() {
+            Err(ConfigError::Invalid(message)) => message,
+            other => panic!("expected an invalid config, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let path =
+            std::env::temp_dir().join(format!("rust-rest-config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "shutdown_timeout_secs = 10\nreadiness_timeout_ms = 11\n\
+             [storage]\nbackend = \"memory\"\n[cache]\nttl_secs = 12\n",
+        )
+        .unwrap();
+        //The only test that sets these, the environment is shared by every test
+        std::env::set_var("READINESS_TIMEOUT_MS", "21");
+        std::env::set_var("CACHE_TTL_SECS", "22");
+        let cli = Cli::try_parse_from([
+            "rust-rest",
+            "--config",
+            path.to_str().unwrap(),
+            "--cache-ttl-secs",
+            "32",
+        ])
+        .unwrap();
+        let config = cli.load_config();
+        std::env::remove_var("READINESS_TIMEOUT_MS");
+        std::env::remove_var("CACHE_TTL_SECS");
+        std::fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        //The file beats the default, the environment the file and flags the environment
+        assert_eq!(config.shutdown_timeout_secs, 10);
+        assert_eq!(config.readiness_timeout_ms, 21);
+        assert_eq!(config.cache.ttl_secs, 32);
+        //Untouched by every layer
+        assert_eq!(config.cache.capacity, CacheConfig::default().capacity);
+    }
+
+    #[test]
+    fn the_example_file_parses() {
+        assert!(read_file(Path::new("config.example.toml")).is_ok());
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(config().validate().is_ok());
+    }
+
+    #[test]
+    fn zero_cache_capacity_is_rejected() {
+        let mut config = config();
+        config.cache.capacity = 0;
+        assert_eq!(invalid(&config), "cache.capacity must be at least 1");
+    }
+
+    #[test]
+    fn fewer_than_two_connections_are_rejected() {
+        let mut config = config();
+        config.database.max_connections = 1;
+        assert_eq!(
+            invalid(&config),
+            "database.max_connections must be at least 2"
+        );
+    }
+
+    #[test]
+    fn cors_origins_must_be_urls_or_any() {
+        let mut config = config();
+        config.cors_origins = vec!["example.com".to_string()];
+        assert_eq!(
+            invalid(&config),
+            "cors_origins entry \"example.com\" must be \"*\" or start with http:// or https://"
+        );
+        config.cors_origins = vec!["*".to_string(), "https://example.com".to_string()];
+        assert!(config.validate().is_ok());
+    }
+}
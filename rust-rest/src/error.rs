@@ -0,0 +1,148 @@
+use crate::{precondition, Question};
+use axum::{
+    extract::rejection::JsonRejection,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+//Postgres SQLSTATE codes we map to client errors
+const UNIQUE_VIOLATION: &str = "23505";
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+
+//Foreign keys by the names postgres generated for them in migrations/
+const ANSWER_QUESTION_FKEY: &str = "answers_question_id_fkey";
+const QUESTION_AUTHOR_FKEY: &str = "questions_author_id_fkey";
+const ANSWER_AUTHOR_FKEY: &str = "answers_author_id_fkey";
+
+//The body of every error response
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ErrorBody {
+    pub error: String,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    ParseE(String),
+    QuestionNotFound,
+    AnswerNotFound,
+    TagSynonymNotFound,
+    UserNotFound,
+    Unauthorized(String),
+    Forbidden(String),
+    Validation(String),
+    Conflict(String),
+    //If-Match names another version than the current one, which is returned instead
+    PreconditionFailed(Box<Question>),
+    //If-Match was left out but Config::require_if_match is on
+    PreconditionRequired,
+    Database(sqlx::Error),
+    Storage(std::io::Error),
+    Internal(String),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        let (status, error_message) = match self {
+            Error::ParseE(e) => (StatusCode::BAD_REQUEST, e),
+            Error::QuestionNotFound => (StatusCode::NOT_FOUND, "Question not found".to_string()),
+            Error::AnswerNotFound => (StatusCode::NOT_FOUND, "Answer not found".to_string()),
+            Error::TagSynonymNotFound => {
+                (StatusCode::NOT_FOUND, "Tag synonym not found".to_string())
+            }
+            Error::UserNotFound => (StatusCode::NOT_FOUND, "User not found".to_string()),
+            Error::Unauthorized(e) => (StatusCode::UNAUTHORIZED, e),
+            Error::Forbidden(e) => (StatusCode::FORBIDDEN, e),
+            Error::Validation(e) => (StatusCode::UNPROCESSABLE_ENTITY, e),
+            Error::Conflict(e) => (StatusCode::CONFLICT, e),
+            //The client merges its edit into the current question and retries with its ETag
+            Error::PreconditionFailed(current) => {
+                return (
+                    StatusCode::PRECONDITION_FAILED,
+                    [(header::ETAG, precondition::etag(current.version))],
+                    Json(*current),
+                )
+                    .into_response()
+            }
+            Error::PreconditionRequired => (
+                StatusCode::PRECONDITION_REQUIRED,
+                "If-Match header with the question's ETag is required".to_string(),
+            ),
+            //Never leak driver messages to clients, only log them
+            Error::Database(e) => {
+                tracing::error!(error = %e, "Database error");
+                match e {
+                    sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Database unavailable".to_string(),
+                    ),
+                    _ => (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Database error".to_string(),
+                    ),
+                }
+            }
+            Error::Storage(e) => {
+                tracing::error!(error = %e, "Storage error");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Storage error".to_string(),
+                )
+            }
+            Error::Internal(e) => {
+                tracing::error!(error = %e, "Internal error");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal server error".to_string(),
+                )
+            }
+        };
+
+        let body = Json(ErrorBody {
+            error: error_message,
+        });
+        (status, body).into_response()
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        if let sqlx::Error::Database(db_error) = &e {
+            match db_error.code().as_deref() {
+                Some(UNIQUE_VIOLATION) => {
+                    return Error::Conflict("A record with this id already exists".to_string())
+                }
+                Some(FOREIGN_KEY_VIOLATION) => match db_error.constraint() {
+                    Some(ANSWER_QUESTION_FKEY) => return Error::QuestionNotFound,
+                    //The author comes from the token, so its account was deleted since
+                    Some(QUESTION_AUTHOR_FKEY | ANSWER_AUTHOR_FKEY) => {
+                        return Error::Unauthorized(
+                            "The signed in account no longer exists".to_string(),
+                        )
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        Error::Database(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Storage(e)
+    }
+}
+
+impl From<JsonRejection> for Error {
+    fn from(rejection: JsonRejection) -> Self {
+        //Well formed JSON with the wrong fields is a validation error, anything else is a bad request
+        match rejection {
+            JsonRejection::JsonDataError(_) => Error::Validation(rejection.body_text()),
+            _ => Error::ParseE(rejection.body_text()),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::error::Error;
+use axum::{
+    async_trait,
+    extract::{rejection::JsonRejection, FromRequest},
+    http::Request,
+    Json,
+};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+
+//Same as axum's Json extractor, but bad bodies are reported as `{ "error": ... }`
+pub struct JsonBody<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for JsonBody<T>
+where
+    Json<T>: FromRequest<S, B, Rejection = JsonRejection>,
+    T: DeserializeOwned,
+    S: Send + Sync,
+    B: Send + 'static,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        Ok(JsonBody(value))
+    }
+}
+
+//For `#[serde(default, deserialize_with = "nullable")]` on an `Option<Option<T>>` field:
+//None when the field is left out, Some(None) when it is null
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
@@ -0,0 +1,125 @@
+use crate::{config::Config, Store};
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+//Flipped when shutdown starts so load balancers stop sending new traffic
+#[derive(Default)]
+pub struct Readiness {
+    shutting_down: AtomicBool,
+}
+
+impl Readiness {
+    pub fn shutting_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+}
+
+//A passing check, with any extra fields from details merged in
+fn ok(details: Value) -> Value {
+    let mut check = json!({ "status": "ok" });
+    if let (Some(check), Value::Object(details)) = (check.as_object_mut(), details) {
+        check.extend(details);
+    }
+    check
+}
+
+fn failed(error: impl ToString) -> Value {
+    json!({ "status": "error", "error": error.to_string() })
+}
+
+//Handler for liveness, answers as long as the process can serve requests at all
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "operations",
+    responses((status = 200, description = "The process is alive", body = Object))
+)]
+pub async fn healthz() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+//Handler for readiness: the database answers within the timeout, every migration
+//is applied and the server is not shutting down. Also reports how full the cache is.
+//Responds 503 with the same body when any check fails
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Every check passed", body = Object),
+        (status = 503, description = "At least one check failed, see checks", body = Object),
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn readyz(
+    State(store): State<Arc<Store>>,
+    State(readiness): State<Arc<Readiness>>,
+    State(config): State<Arc<Config>>,
+) -> (StatusCode, Json<Value>) {
+    let timeout = Duration::from_millis(config.readiness_timeout_ms);
+    let mut checks = serde_json::Map::new();
+
+    checks.insert(
+        "shutdown".to_string(),
+        if readiness.shutting_down.load(Ordering::SeqCst) {
+            failed("Shutting down")
+        } else {
+            ok(json!({}))
+        },
+    );
+
+    //Filled as questions are read, so this only reports how full it is
+    let (questions, answers) = store.cache_sizes();
+    checks.insert(
+        "cache".to_string(),
+        ok(json!({
+            "questions": questions,
+            "answers": answers,
+            "capacity": store.cache_capacity(),
+        })),
+    );
+
+    let start = Instant::now();
+    let database = match tokio::time::timeout(timeout, store.repo.ping()).await {
+        Ok(Ok(())) => ok(json!({ "latency_ms": start.elapsed().as_millis() as u64 })),
+        //The body is public, the driver message only goes to the log
+        Ok(Err(e)) => {
+            tracing::error!(error = ?e, "Readiness database check failed");
+            failed("Database unavailable")
+        }
+        Err(_) => failed(format!(
+            "No answer within {}ms",
+            config.readiness_timeout_ms
+        )),
+    };
+    checks.insert("database".to_string(), database);
+
+    let migrations = match tokio::time::timeout(timeout, store.repo.migrations_applied()).await {
+        Ok(Ok(true)) => ok(json!({})),
+        Ok(Ok(false)) => failed("Pending migrations"),
+        Ok(Err(e)) => {
+            tracing::error!(error = ?e, "Readiness migration check failed");
+            failed("Migration check failed")
+        }
+        Err(_) => failed(format!(
+            "No answer within {}ms",
+            config.readiness_timeout_ms
+        )),
+    };
+    checks.insert("migrations".to_string(), migrations);
+
+    let ready = checks.values().all(|check| check["status"] == "ok");
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = json!({
+        "status": if ready { "ok" } else { "unavailable" },
+        "checks": checks,
+    });
+    (status, Json(body))
+}
@@ -1,255 +1,1680 @@
+use auth::{AuthKeys, AuthUser};
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
+    extract::{FromRef, Path, Query, State},
+    http::{header, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
     routing::{delete, get, post, put},
     Json, Router, Server,
 };
-use http::HeaderValue;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use sqlx::PgPool;
+use cache::Lru;
+use chrono::{NaiveDateTime, SubsecRound, Utc};
+use clap::Parser;
+use config::{CacheConfig, Cli, Config};
+use error::Error;
+use extract::JsonBody;
+use health::Readiness;
+use http::{HeaderName, HeaderValue};
+use pagination::Page;
+use precondition::IfMatch;
+use repository::{Change, Repository};
+use search::SearchHit;
+use serde::{de::IgnoredAny, Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tower_http::cors::{Any, CorsLayer};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tags::{TagCount, TagMatch, TagSynonym};
+use tokio::sync::{mpsc, Mutex};
+use tower::ServiceBuilder;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tower_http::request_id::{PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tower_http::LatencyUnit;
+use tracing::Level;
+use ulid::Ulid;
+use utoipa::ToSchema;
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+mod auth;
+mod cache;
+mod config;
+mod error;
+mod extract;
+mod health;
+mod metrics;
+mod openapi;
+mod pagination;
+mod policy;
+mod precondition;
+mod repository;
+mod search;
+mod shutdown;
+mod tags;
+mod telemetry;
+
+#[derive(Deserialize, Serialize, ToSchema, Debug, Clone)]
 struct Question {
+    //generated by the server when left out of the body
+    #[serde(default)]
     id: String,
     title: String,
     content: String,
     tags: Option<Vec<String>>,
+    //set by the server, used to order questions
+    #[serde(default)]
+    created_on: NaiveDateTime,
+    //the user who asked, None for questions from before accounts existed
+    #[serde(default)]
+    author_id: Option<String>,
+    //set by the server and bumped on every change, sent as the ETag
+    #[serde(default = "first_version")]
+    version: i64,
+}
+
+impl Question {
+    fn validate(&self) -> Result<(), Error> {
+        if self.title.trim().is_empty() {
+            return Err(Error::Validation(
+                "Question title must not be empty".to_string(),
+            ));
+        }
+        if self.content.trim().is_empty() {
+            return Err(Error::Validation(
+                "Question content must not be empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
 struct QuestionId(String);
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+//Postgres keeps microseconds, so truncate to keep cursors identical across backends
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc().trunc_subsecs(6)
+}
+
+//Version of a new question, and of questions stored before versions existed
+fn first_version() -> i64 {
+    1
+}
+
+//ULIDs are unique without coordination and sort by creation time
+fn generate_id() -> String {
+    Ulid::new().to_string()
+}
+
+//Ids end up in URL paths, so only allow characters that never need escaping.
+//Only checked on create so rows from before this rule can still be edited
+fn validate_id(id: &str) -> Result<(), Error> {
+    if id.is_empty() {
+        return Err(Error::Validation("Id must not be empty".to_string()));
+    }
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~'))
+    {
+        return Err(Error::Validation(
+            "Id may only contain letters, digits, '-', '_', '.' and '~'".to_string(),
+        ));
+    }
+    //Clients and proxies resolve /questions/. and /questions/.. away, so nobody could reach it
+    if id.chars().all(|c| c == '.') {
+        return Err(Error::Validation(
+            "Id must not be made of dots only".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
 struct Answer {
+    //generated by the server when left out of the body
+    #[serde(default)]
     id: String,
     content: String,
+    //taken from the request path, so clients may leave it out of the body
+    #[serde(default)]
     question_id: String,
+    //the user who answered, set by the server
+    #[serde(default)]
+    author_id: Option<String>,
+    //set by the server, used to order the answers to a question
+    #[serde(default)]
+    created_on: NaiveDateTime,
+}
+
+impl Answer {
+    fn validate(&self) -> Result<(), Error> {
+        if self.content.trim().is_empty() {
+            return Err(Error::Validation(
+                "Answer content must not be empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
 struct AnswerId(String);
 
+//Shared by every handler. Handlers extract the part they need through FromRef
 #[derive(Clone)]
+struct AppState {
+    store: Arc<Store>,
+    auth_keys: Arc<AuthKeys>,
+    config: Arc<Config>,
+    readiness: Arc<Readiness>,
+}
+
+impl FromRef<AppState> for Arc<Store> {
+    fn from_ref(state: &AppState) -> Self {
+        state.store.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<AuthKeys> {
+    fn from_ref(state: &AppState) -> Self {
+        state.auth_keys.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Readiness> {
+    fn from_ref(state: &AppState) -> Self {
+        state.readiness.clone()
+    }
+}
+
+//The cache in front of the storage backend. Questions and answers are cached as they
+//are read, up to a fixed number and for a limited time, lists and pages come straight
+//from storage. The cache locks are only held for a lookup or a copy, never across a
+//storage call, so readers run in parallel and never wait for the database on behalf
+//of someone else
 struct Store {
-    questions: HashMap<String, Question>,
-    pool: PgPool,
+    questions: Lru<String, Question>,
+    //question id -> its answers, oldest first
+    answers: Lru<String, Vec<Answer>>,
+    //synonym -> canonical tag, applied to tags on every write. Small enough to keep whole
+    tag_synonyms: RwLock<HashMap<String, String>>,
+    //Held by a write from its first check until the cache is updated, so writes reach
+    //the cache in the order they reached storage. Readers never take it
+    writes: Mutex<()>,
+    repo: Arc<dyn Repository>,
 }
 
 impl Store {
     //constructor for creating an instance of store
-    async fn new(pool: PgPool) -> Self {
-        let questions = Self::init(&pool).await;
-        Store { questions, pool }
-    }
-
-    async fn init(pool: &PgPool) -> HashMap<String, Question> {
-        let mut questions = HashMap::new();
-        //returns a Result<Vec<Question>
-        let records = sqlx::query_as!(Question, "SELECT id, title, content, tags FROM questions")
-            .fetch_all(pool)
-            .await
-            .expect("Failed to fetch questions");
-        for record in records {
-            questions.insert(record.id.clone(), record);
-        }
-        questions
-    }
-}
-
-#[derive(Debug)]
-enum Error {
-    ParseE(String),
-    MissingParameters,
-    QuestionNotFound,
-}
-
-impl IntoResponse for Error {
-    fn into_response(self) -> axum::response::Response {
-        let (status, error_message) = match self {
-            Error::ParseE(e) => (axum::http::StatusCode::BAD_REQUEST, e),
-            Error::MissingParameters => (
-                axum::http::StatusCode::BAD_REQUEST,
-                "Missing required parameters".to_string(),
-            ),
-            Error::QuestionNotFound => (
-                axum::http::StatusCode::NOT_FOUND,
-                "Question not found".to_string(),
-            ),
+    #[tracing::instrument(skip_all)]
+    async fn new(repo: Arc<dyn Repository>, config: &CacheConfig) -> Result<Self, Error> {
+        let ttl = (config.ttl_secs > 0).then(|| Duration::from_secs(config.ttl_secs));
+        let tag_synonyms = Self::load_tag_synonyms(repo.as_ref()).await?;
+        Ok(Store {
+            questions: Lru::new("questions", config.capacity, ttl),
+            answers: Lru::new("answers", config.capacity, ttl),
+            tag_synonyms: RwLock::new(tag_synonyms),
+            writes: Mutex::new(()),
+            repo,
+        })
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn load_tag_synonyms(repo: &dyn Repository) -> Result<HashMap<String, String>, Error> {
+        let synonyms = repo
+            .list_tag_synonyms()
+            .await?
+            .into_iter()
+            .map(|synonym| (synonym.synonym, synonym.canonical))
+            .collect();
+        Ok(synonyms)
+    }
+
+    //Number of cached questions, and of questions whose answers are cached
+    fn cache_sizes(&self) -> (usize, usize) {
+        (self.questions.len(), self.answers.len())
+    }
+
+    //Same for both caches
+    fn cache_capacity(&self) -> usize {
+        self.questions.capacity()
+    }
+
+    fn canonical_tag(&self, tag: &str) -> String {
+        tags::canonical(tag, &self.tag_synonyms.read().unwrap())
+    }
+
+    fn normalize_tags(&self, question_tags: Option<Vec<String>>) -> Option<Vec<String>> {
+        tags::normalize(question_tags, &self.tag_synonyms.read().unwrap())
+    }
+
+    //Sorted by synonym
+    fn list_tag_synonyms(&self) -> Vec<TagSynonym> {
+        let mut synonyms: Vec<TagSynonym> = self
+            .tag_synonyms
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(synonym, canonical)| TagSynonym {
+                synonym: synonym.clone(),
+                canonical: canonical.clone(),
+            })
+            .collect();
+        synonyms.sort_by(|a, b| a.synonym.cmp(&b.synonym));
+        synonyms
+    }
+
+    //Retagging changes any number of questions in storage, so cached ones are read again
+    fn retagged(&self) {
+        self.questions.clear();
+    }
+
+    //Retags `from` as `into` in storage and then in the cache, returns the number of questions changed.
+    //The caller holds `writes`
+    #[tracing::instrument(skip(self))]
+    async fn merge_tags(&self, from: Vec<String>, into: &str) -> Result<u64, Error> {
+        let into = self.canonical_tag(into);
+        if into.is_empty() {
+            return Err(Error::Validation("Tag name must not be empty".to_string()));
+        }
+        let mut from: Vec<String> = from
+            .iter()
+            .map(|tag| tags::normalize_name(tag))
+            .filter(|tag| !tag.is_empty() && *tag != into)
+            .collect();
+        from.sort();
+        from.dedup();
+        if from.is_empty() {
+            return Err(Error::Validation(
+                "Nothing to merge, give at least one tag other than the target".to_string(),
+            ));
+        }
+
+        let updated = self.repo.merge_tags(&from, &into).await?;
+        tags::merge_synonyms(&mut self.tag_synonyms.write().unwrap(), &from, &into);
+        self.retagged();
+        Ok(updated)
+    }
+
+    //Checks the cache first, then the storage backend, caching what it finds there
+    #[tracing::instrument(skip(self))]
+    async fn find_question(&self, id: &str) -> Result<Option<Question>, Error> {
+        if let Some(question) = self.questions.get(id) {
+            return Ok(Some(question));
+        }
+        let generation = self.questions.generation();
+        let question = self.repo.get_question(id).await?;
+        if let Some(question) = &question {
+            self.questions
+                .fill(id.to_string(), question.clone(), generation);
+        }
+        Ok(question)
+    }
+
+    //Same as find_question, for the answers to one question
+    #[tracing::instrument(skip(self))]
+    async fn find_answers(&self, question_id: &str) -> Result<Vec<Answer>, Error> {
+        if let Some(answers) = self.answers.get(question_id) {
+            return Ok(answers);
+        }
+        let generation = self.answers.generation();
+        let answers = self.repo.answers_for_question(question_id).await?;
+        self.answers
+            .fill(question_id.to_string(), answers.clone(), generation);
+        Ok(answers)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn question_exists(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.find_question(id).await?.is_some())
+    }
+
+    //Storage deletes the answers with their question, so the cache does too
+    fn uncache_question(&self, id: &str) {
+        self.answers.remove(id);
+        self.questions.remove(id);
+    }
+
+    //The answers are read again the next time they are asked for
+    fn uncache_answers(&self, question_id: &str) {
+        self.answers.remove(question_id);
+    }
+
+    //Every write to questions and answers ends here. When the write failed, or found no row,
+    //storage may no longer match the cached copy, so `forget` drops it before the error is
+    //returned and the next read goes to storage
+    fn settle<T>(result: Result<T, Error>, forget: impl FnOnce()) -> Result<T, Error> {
+        if result.is_err() {
+            forget();
+        }
+        result
+    }
+
+    //The write methods below go to storage first and only update the cache once storage
+    //has the change. The caller holds `writes`
+
+    #[tracing::instrument(skip_all, fields(question_id = %question.id))]
+    async fn insert_question(&self, question: &Question) -> Result<(), Error> {
+        let result = self.repo.insert_question(question).await;
+        Self::settle(result, || self.uncache_question(&question.id))?;
+        self.questions.insert(question.id.clone(), question.clone());
+        Ok(())
+    }
+
+    //Why storage refused a question write at `versions`: the question is gone, or it is at
+    //another version and the current one goes back to the client. Either way the cached
+    //copy was stale, so it is replaced by what storage has now
+    async fn refused(&self, id: &str, versions: Option<&[i64]>) -> Error {
+        let current = match versions {
+            Some(_) => self.repo.get_question(id).await,
+            None => Ok(None),
+        };
+        match current {
+            Ok(Some(current)) => {
+                self.questions.insert(id.to_string(), current.clone());
+                Error::PreconditionFailed(Box::new(current))
+            }
+            Ok(None) => {
+                self.uncache_question(id);
+                Error::QuestionNotFound
+            }
+            Err(e) => {
+                self.uncache_question(id);
+                e
+            }
+        }
+    }
+
+    //Returns the question as stored under `id`, whatever id the body carries.
+    //Only writes when the question is still at one of `versions`, when given
+    #[tracing::instrument(skip(self, question))]
+    async fn update_question(
+        &self,
+        id: &str,
+        question: &Question,
+        versions: Option<&[i64]>,
+    ) -> Result<Question, Error> {
+        let result = self.repo.update_question(id, question, versions).await;
+        let Some(stored) = Self::settle(result, || self.uncache_question(id))? else {
+            return Err(self.refused(id, versions).await);
         };
+        self.questions.insert(id.to_string(), stored.clone());
+        Ok(stored)
+    }
 
-        let body = Json(json!({ "error": error_message }));
-        (status, body).into_response()
+    //Same as update_question, for the fields in the patch only
+    #[tracing::instrument(skip(self, patch))]
+    async fn patch_question(
+        &self,
+        id: &str,
+        patch: &QuestionPatch,
+        versions: Option<&[i64]>,
+    ) -> Result<Question, Error> {
+        let result = self.repo.patch_question(id, patch, versions).await;
+        let Some(stored) = Self::settle(result, || self.uncache_question(id))? else {
+            return Err(self.refused(id, versions).await);
+        };
+        self.questions.insert(id.to_string(), stored.clone());
+        Ok(stored)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_question(&self, id: &str, versions: Option<&[i64]>) -> Result<(), Error> {
+        let result = self.repo.delete_question(id, versions).await;
+        if !Self::settle(result, || self.uncache_question(id))? {
+            return Err(self.refused(id, versions).await);
+        }
+        self.uncache_question(id);
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(question_id = %answer.question_id, answer_id = %answer.id))]
+    async fn insert_answer(&self, answer: &Answer) -> Result<(), Error> {
+        let result = self.repo.insert_answer(answer).await;
+        //The question may have been deleted elsewhere while it was still cached here
+        if matches!(result, Err(Error::QuestionNotFound)) {
+            self.uncache_question(&answer.question_id);
+        }
+        Self::settle(result, || self.uncache_answers(&answer.question_id))?;
+        self.uncache_answers(&answer.question_id);
+        Ok(())
+    }
+
+    //Returns the answer as stored. Only updates it when it belongs to `answer.question_id`
+    #[tracing::instrument(skip_all, fields(question_id = %answer.question_id, answer_id = %answer.id))]
+    async fn update_answer(&self, answer: &Answer) -> Result<Answer, Error> {
+        let result = self.repo.update_answer(&answer.id, answer).await;
+        let stored = Self::settle(
+            result.and_then(|stored| stored.ok_or(Error::AnswerNotFound)),
+            || self.uncache_answers(&answer.question_id),
+        )?;
+        self.uncache_answers(&answer.question_id);
+        Ok(stored)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_answer(&self, question_id: &str, id: &str) -> Result<(), Error> {
+        let result = self.repo.delete_answer(question_id, id).await;
+        let deleted = Self::settle(result, || self.uncache_answers(question_id))?;
+        self.uncache_answers(question_id);
+        if deleted {
+            Ok(())
+        } else {
+            Err(Error::AnswerNotFound)
+        }
+    }
+
+    //Tag synonyms are only ever changed under `writes`
+    async fn reload_tag_synonyms(&self) -> Result<(), Error> {
+        let _writes = metrics::lock_writes(&self.writes).await;
+        let tag_synonyms = Self::load_tag_synonyms(self.repo.as_ref()).await?;
+        *self.tag_synonyms.write().unwrap() = tag_synonyms;
+        Ok(())
+    }
+
+    //Drops what the change makes stale, the next read fetches it from storage again
+    #[tracing::instrument(skip(self))]
+    async fn apply_change(&self, change: Change) -> Result<(), Error> {
+        match change {
+            Change::Question(id) => self.questions.remove(&id),
+            Change::Answer(question_id) => self.uncache_answers(&question_id),
+            Change::TagSynonyms => {
+                self.reload_tag_synonyms().await?;
+                tracing::info!("Tag synonyms reloaded from storage");
+            }
+            Change::Resync => {
+                self.reload_tag_synonyms().await?;
+                self.questions.clear();
+                self.answers.clear();
+                tracing::info!("Cache resynced with storage");
+            }
+        }
+        Ok(())
+    }
+}
+
+//Applies changes from the storage backend's feed to the cache until the feed ends
+async fn follow_changes(store: Arc<Store>, mut changes: mpsc::Receiver<Change>) {
+    while let Some(change) = changes.recv().await {
+        metrics::record_cache_change(&change);
+        if let Err(e) = store.apply_change(change).await {
+            tracing::error!(error = ?e, "Failed to apply a change to the cache");
+        }
     }
 }
 
-//Handler to get ALL questions
-async fn questions(State(store): State<Arc<Mutex<Store>>>) -> Result<Json<Vec<Question>>, Error> {
-    let store = store.lock().await;
-    let questions = store.questions.values().cloned().collect();
+//Handler to get ALL questions, or only those with the given `tag`s when any are passed.
+//`tag` may be repeated and `match=all` requires every tag instead of any of them
+#[tracing::instrument(skip_all)]
+async fn questions(
+    Query(params): Query<Vec<(String, String)>>,
+    State(store): State<Arc<Store>>,
+) -> Result<Json<Vec<Question>>, Error> {
+    let tag_match = TagMatch::parse(
+        params
+            .iter()
+            .find(|(key, _)| key == "match")
+            .map(|(_, value)| value.as_str()),
+    )?;
+
+    let tags: Vec<String> = params
+        .iter()
+        .filter(|(key, _)| key == "tag")
+        .map(|(_, value)| store.canonical_tag(value))
+        .collect();
+    if tags.is_empty() {
+        return Ok(Json(store.repo.list_questions().await?));
+    }
+    let questions = store.repo.questions_with_tags(&tags, tag_match).await?;
     Ok(Json(questions))
 }
 
-// Hanlder for get_questions to get paginated questions
+//Handler to get every tag with the number of questions using it
+#[utoipa::path(
+    get,
+    path = "/api/v1/tags",
+    tag = "tags",
+    responses((status = 200, description = "Tags with their question counts", body = [TagCount]))
+)]
+#[tracing::instrument(skip_all)]
+async fn tags(State(store): State<Arc<Store>>) -> Result<Json<Vec<TagCount>>, Error> {
+    let counts = store.repo.tag_counts().await?;
+    Ok(Json(counts))
+}
+
+//Handler to get the questions with a single tag
+#[utoipa::path(
+    get,
+    path = "/api/v1/tags/{name}/questions",
+    tag = "tags",
+    params(("name" = String, Path, description = "Tag, or a synonym of it")),
+    responses((status = 200, description = "Questions with the tag", body = [Question]))
+)]
+#[tracing::instrument(skip_all, fields(tag = %tag))]
+async fn tag_questions(
+    Path(tag): Path<String>,
+    State(store): State<Arc<Store>>,
+) -> Result<Json<Vec<Question>>, Error> {
+    let tag = store.canonical_tag(&tag);
+    let questions = store
+        .repo
+        .questions_with_tags(&[tag], TagMatch::Any)
+        .await?;
+    Ok(Json(questions))
+}
+
+// Hanlder for get_questions to get paginated questions.
+// `cursor`/`limit` return a page envelope, `start`/`end` keep returning a plain list for the Yew paginate form
+#[tracing::instrument(skip_all)]
 async fn get_question(
     Query(params): Query<HashMap<String, String>>,
-    State(store): State<Arc<Mutex<Store>>>,
-) -> Result<Json<Vec<Question>>, Error> {
-    let store = store.lock().await;
+    State(store): State<Arc<Store>>,
+) -> Result<Response, Error> {
+    if params.contains_key("start") || params.contains_key("end") {
+        let start = params
+            .get("start")
+            .and_then(|v| v.parse::<usize>().ok())
+            .ok_or(Error::ParseE("Invalid start parameter".to_string()))?;
+
+        let end = params
+            .get("end")
+            .and_then(|v| v.parse::<usize>().ok())
+            .ok_or(Error::ParseE("Invalid end parameter".to_string()))?;
+
+        if start >= end {
+            return Err(Error::ParseE(
+                "End parameter must be greater than start".to_string(),
+            ));
+        }
+
+        //At most MAX_LIMIT questions, like a page. Past the end is a short (or empty) page, not an error
+        let limit = (end - start).min(pagination::MAX_LIMIT);
+        let questions = store.repo.questions_range(start, limit).await?;
+        return Ok(Json(questions).into_response());
+    }
+
+    let limit = pagination::parse_limit(&params)?;
+
+    let cursor = params
+        .get("cursor")
+        .map(|v| pagination::Cursor::decode(v))
+        .transpose()?;
+
+    let page = store
+        .repo
+        .questions_page(&[], TagMatch::Any, cursor.as_ref(), limit)
+        .await?;
+    Ok(Json(page).into_response())
+}
+
+//Handler to list questions a page at a time, optionally only those with the given `tag`s.
+//Takes the `tag`/`match` filter of /questions and the `cursor`/`limit` of /question
+#[utoipa::path(
+    get,
+    path = "/api/v1/questions",
+    tag = "questions",
+    params(
+        ("tag" = Option<Vec<String>>, Query, description = "Only questions with these tags, may be repeated"),
+        ("match" = Option<String>, Query, description = "`any` (default) or `all` of the tags"),
+        ("limit" = Option<usize>, Query, description = "Page size, 20 by default and at most 100"),
+        ("cursor" = Option<String>, Query, description = "`next_cursor` of the previous page"),
+    ),
+    responses(
+        (status = 200, description = "A page of questions, oldest first", body = QuestionPage),
+        (status = 400, description = "Invalid match, limit or cursor", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all)]
+async fn list_questions(
+    Query(params): Query<Vec<(String, String)>>,
+    State(store): State<Arc<Store>>,
+) -> Result<Json<Page<Question>>, Error> {
+    //Every parameter but `tag` appears at most once
+    let single: HashMap<String, String> = params
+        .iter()
+        .filter(|(key, _)| key != "tag")
+        .cloned()
+        .collect();
+    let tag_match = TagMatch::parse(single.get("match").map(String::as_str))?;
+    let limit = pagination::parse_limit(&single)?;
+    let cursor = single
+        .get("cursor")
+        .map(|v| pagination::Cursor::decode(v))
+        .transpose()?;
+
+    let tags: Vec<String> = params
+        .iter()
+        .filter(|(key, _)| key == "tag")
+        .map(|(_, value)| store.canonical_tag(value))
+        .collect();
+    let page = store
+        .repo
+        .questions_page(&tags, tag_match, cursor.as_ref(), limit)
+        .await?;
+    Ok(Json(page))
+}
+
+//A question with the related records asked for through `include`
+#[derive(Serialize, ToSchema, Debug)]
+struct QuestionDetail {
+    #[serde(flatten)]
+    question: Question,
+    //Only present with include=answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    answers: Option<Vec<Answer>>,
+}
+
+//Handler to get a single question, `include=answers` embeds its answers
+#[utoipa::path(
+    get,
+    path = "/api/v1/questions/{id}",
+    tag = "questions",
+    params(
+        ("id" = String, Path, description = "Question id"),
+        ("include" = Option<String>, Query, description = "`answers` to embed the question's answers, oldest first"),
+    ),
+    responses(
+        (status = 200, description = "The question", body = QuestionDetail,
+            headers(("ETag" = String, description = "The question's version, for If-Match"))),
+        (status = 400, description = "Unknown include", body = ErrorBody),
+        (status = 404, description = "Question not found", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(question_id = %question_id))]
+async fn question_by_id(
+    Path(QuestionId(question_id)): Path<QuestionId>,
+    Query(params): Query<HashMap<String, String>>,
+    State(store): State<Arc<Store>>,
+) -> Result<impl IntoResponse, Error> {
+    //A comma separated list, so more relations can be added later
+    let mut include_answers = false;
+    for include in params.get("include").into_iter().flat_map(|v| v.split(',')) {
+        match include.trim() {
+            "answers" => include_answers = true,
+            "" => {}
+            other => {
+                return Err(Error::ParseE(format!(
+                    "Unknown include {other:?}, expected \"answers\""
+                )))
+            }
+        }
+    }
+
+    let question = store
+        .find_question(&question_id)
+        .await?
+        .ok_or(Error::QuestionNotFound)?;
+    let answers = if include_answers {
+        Some(store.find_answers(&question_id).await?)
+    } else {
+        None
+    };
+    //The version of the question only, answers can change without it
+    let etag = precondition::etag(question.version);
+    Ok((
+        [(header::ETAG, etag)],
+        Json(QuestionDetail { question, answers }),
+    ))
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+struct RenameTag {
+    from: String,
+    to: String,
+}
 
-    if params.is_empty() {
-        return Err(Error::MissingParameters);
+#[derive(Deserialize, ToSchema, Debug)]
+struct MergeTags {
+    from: Vec<String>,
+    into: String,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+struct Retagged {
+    //Number of questions whose tags changed
+    updated: u64,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+struct SynonymAdded {
+    synonym: TagSynonym,
+    //Number of questions retagged with the canonical tag
+    updated: u64,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+struct Message {
+    message: String,
+}
+
+impl Message {
+    fn new(message: &str) -> Json<Message> {
+        Json(Message {
+            message: message.to_string(),
+        })
     }
+}
 
-    let start = params
-        .get("start")
-        .and_then(|v| v.parse::<usize>().ok())
-        .ok_or(Error::ParseE("Invalid start parameter".to_string()))?;
+//Handler to rename a tag on every question
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/tags/rename",
+    tag = "admin",
+    request_body = RenameTag,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Tag renamed", body = Retagged),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+        (status = 422, description = "Empty tag name", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id, from = %rename.from, to = %rename.to))]
+async fn rename_tag(
+    State(store): State<Arc<Store>>,
+    user: AuthUser,
+    JsonBody(rename): JsonBody<RenameTag>,
+) -> Result<Json<Retagged>, Error> {
+    policy::can_manage_tags(&user)?;
+
+    let _writes = metrics::lock_writes(&store.writes).await;
+    let updated = store.merge_tags(vec![rename.from], &rename.to).await?;
+    Ok(Json(Retagged { updated }))
+}
+
+//Handler to merge several tags into one on every question
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/tags/merge",
+    tag = "admin",
+    request_body = MergeTags,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Tags merged", body = Retagged),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+        (status = 422, description = "Nothing to merge", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id, into = %merge.into))]
+async fn merge_tags(
+    State(store): State<Arc<Store>>,
+    user: AuthUser,
+    JsonBody(merge): JsonBody<MergeTags>,
+) -> Result<Json<Retagged>, Error> {
+    policy::can_manage_tags(&user)?;
 
-    let end = params
-        .get("end")
-        .and_then(|v| v.parse::<usize>().ok())
-        .ok_or(Error::ParseE("Invalid end parameter".to_string()))?;
+    let _writes = metrics::lock_writes(&store.writes).await;
+    let updated = store.merge_tags(merge.from, &merge.into).await?;
+    Ok(Json(Retagged { updated }))
+}
+
+//Handler to list tag synonyms
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/tags/synonyms",
+    tag = "admin",
+    responses((status = 200, description = "Every synonym, sorted", body = [TagSynonym]))
+)]
+#[tracing::instrument(skip_all)]
+async fn tag_synonyms(State(store): State<Arc<Store>>) -> Json<Vec<TagSynonym>> {
+    Json(store.list_tag_synonyms())
+}
+
+//Handler to add a tag synonym, existing questions are retagged with the canonical tag
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/tags/synonyms",
+    tag = "admin",
+    request_body = TagSynonym,
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "Synonym added", body = SynonymAdded),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+        (status = 409, description = "The synonym already exists", body = ErrorBody),
+        (status = 422, description = "Invalid synonym", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id))]
+async fn add_tag_synonym(
+    State(store): State<Arc<Store>>,
+    user: AuthUser,
+    JsonBody(synonym): JsonBody<TagSynonym>,
+) -> Result<impl IntoResponse, Error> {
+    policy::can_manage_tags(&user)?;
 
-    if start >= end {
-        return Err(Error::ParseE(
-            "End parameter must be greater than start".to_string(),
+    let synonym = TagSynonym {
+        synonym: tags::normalize_name(&synonym.synonym),
+        canonical: tags::normalize_name(&synonym.canonical),
+    };
+    if synonym.synonym.is_empty() || synonym.canonical.is_empty() {
+        return Err(Error::Validation("Tag name must not be empty".to_string()));
+    }
+    if synonym.synonym == synonym.canonical {
+        return Err(Error::Validation(
+            "A tag cannot be a synonym of itself".to_string(),
         ));
     }
 
-    let res: Vec<Question> = store.questions.values().cloned().collect();
+    let _writes = metrics::lock_writes(&store.writes).await;
+    //Only one level of synonyms, so lookups never have to follow a chain
+    {
+        let synonyms = store.tag_synonyms.read().unwrap();
+        if synonyms.contains_key(&synonym.canonical) {
+            return Err(Error::Validation(format!(
+                "{} is itself a synonym",
+                synonym.canonical
+            )));
+        }
+        if synonyms.values().any(|c| *c == synonym.synonym) {
+            return Err(Error::Validation(format!(
+                "{} already has synonyms",
+                synonym.synonym
+            )));
+        }
+    }
 
-    if start >= res.len() || end > res.len() {
-        return Err(Error::QuestionNotFound);
+    let updated = store.repo.insert_tag_synonym(&synonym).await?;
+    store.retagged();
+    store
+        .tag_synonyms
+        .write()
+        .unwrap()
+        .insert(synonym.synonym.clone(), synonym.canonical.clone());
+
+    Ok((StatusCode::CREATED, Json(SynonymAdded { synonym, updated })))
+}
+
+//Handler to delete a tag synonym, questions keep their canonical tag
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/tags/synonyms/{synonym}",
+    tag = "admin",
+    params(("synonym" = String, Path, description = "Synonym to remove")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Synonym deleted", body = Message),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+        (status = 404, description = "Tag synonym not found", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id, synonym = %synonym))]
+async fn delete_tag_synonym(
+    user: AuthUser,
+    Path(synonym): Path<String>,
+    State(store): State<Arc<Store>>,
+) -> Result<impl IntoResponse, Error> {
+    policy::can_manage_tags(&user)?;
+
+    let _writes = metrics::lock_writes(&store.writes).await;
+    let synonym = tags::normalize_name(&synonym);
+    if !store.repo.delete_tag_synonym(&synonym).await? {
+        return Err(Error::TagSynonymNotFound);
     }
+    store.tag_synonyms.write().unwrap().remove(&synonym);
 
-    Ok(Json(res[start..end].to_vec()))
+    Ok((
+        StatusCode::OK,
+        Message::new("Tag synonym deleted successfully"),
+    ))
+}
+
+//Handler to search questions by title, content and tags
+#[utoipa::path(
+    get,
+    path = "/api/v1/questions/search",
+    tag = "questions",
+    params(
+        ("q" = String, Query, description = "Search terms"),
+        ("limit" = Option<usize>, Query, description = "Number of hits, 20 by default and at most 100"),
+    ),
+    responses(
+        (status = 200, description = "Best match first", body = [SearchHit]),
+        (status = 400, description = "Missing q or invalid limit", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all)]
+async fn search_questions(
+    Query(params): Query<HashMap<String, String>>,
+    State(store): State<Arc<Store>>,
+) -> Result<Json<Vec<SearchHit>>, Error> {
+    let query = params
+        .get("q")
+        .map(|q| q.trim())
+        .filter(|q| !q.is_empty())
+        .ok_or(Error::ParseE("Missing q parameter".to_string()))?;
+    let limit = pagination::parse_limit(&params)?;
+
+    let hits = store.repo.search_questions(query, limit).await?;
+    Ok(Json(hits))
 }
 
 // Handler to add a new question
+#[utoipa::path(
+    post,
+    path = "/api/v1/questions",
+    tag = "questions",
+    request_body = Question,
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "Question created, see the Location header", body = Question,
+            headers(("ETag" = String, description = "The question's version, for If-Match"))),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 409, description = "A question with this id already exists", body = ErrorBody),
+        (status = 422, description = "Invalid question", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id, question_id))]
 async fn add_question(
-    State(store): State<Arc<Mutex<Store>>>,
-    Json(question): Json<Question>,
-) -> impl IntoResponse {
-    //Access the Store object first by acquiring a write lock
-    let mut store = store.lock().await;
-    sqlx::query!(
-        "INSERT INTO questions (id, title, content, tags) VALUES ($1, $2, $3, $4)",
-        question.id,
-        question.title,
-        question.content,
-        question.tags.as_deref()
-    )
-    .execute(&store.pool)
-    .await
-    .expect("Failed to insert question");
-
-    //Insert the question into the HashMap
-    store.questions.insert(question.id.clone(), question);
+    State(store): State<Arc<Store>>,
+    user: AuthUser,
+    JsonBody(mut question): JsonBody<Question>,
+) -> Result<impl IntoResponse, Error> {
+    if question.id.is_empty() {
+        question.id = generate_id();
+    }
+    validate_id(&question.id)?;
+    tracing::Span::current().record("question_id", question.id.as_str());
+    question.validate()?;
+    question.created_on = now();
+    question.author_id = Some(user.id);
+    question.version = first_version();
 
-    //Return a response
-    Response::builder()
-        .status(StatusCode::CREATED)
-        .body("Question added".to_string())
-        .unwrap()
+    //Wait for other writes, readers carry on meanwhile
+    let _writes = metrics::lock_writes(&store.writes).await;
+    question.tags = store.normalize_tags(question.tags);
+    store.insert_question(&question).await?;
+
+    //Return the created question and where to find it
+    let location = format!("/api/v1/questions/{}", question.id);
+    let etag = precondition::etag(question.version);
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, location), (header::ETAG, etag)],
+        Json(question),
+    ))
 }
 
 // Handler to update an existing question
+#[utoipa::path(
+    put,
+    path = "/api/v1/questions/{id}",
+    tag = "questions",
+    params(
+        ("id" = String, Path, description = "Question id"),
+        ("If-Match" = Option<String>, Header, description = "ETags of the versions the change may be based on, comma-separated, or `*`. Required when require_if_match is on"),
+    ),
+    request_body = Question,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Question updated", body = String, content_type = "text/plain",
+            headers(("ETag" = String, description = "The new version, for If-Match"))),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Not the author or a moderator", body = ErrorBody),
+        (status = 404, description = "Question not found", body = ErrorBody),
+        (status = 412, description = "Changed since the If-Match version, this is the current question", body = Question),
+        (status = 422, description = "Invalid question", body = ErrorBody),
+        (status = 428, description = "If-Match is required", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id, question_id = %question_id))]
 async fn update_question(
-    State(store): State<Arc<Mutex<Store>>>,
+    State(store): State<Arc<Store>>,
+    user: AuthUser,
     Path(question_id): Path<String>,
-    Json(updated_question): Json<Question>,
-) -> impl IntoResponse {
-    //Access the Store object first by acquiring a write lock
-    let mut store = store.lock().await;
+    IfMatch(versions): IfMatch,
+    JsonBody(mut updated_question): JsonBody<Question>,
+) -> Result<impl IntoResponse, Error> {
+    //The path is authoritative, an id in the body is ignored
+    updated_question.id = question_id.clone();
+    updated_question.validate()?;
 
-    // Execute the SQL update query
-    sqlx::query!(
-        "UPDATE questions SET title = $2, content = $3, tags = $4 WHERE id = $1",
-        question_id,
-        updated_question.title,
-        updated_question.content,
-        updated_question.tags.as_deref()
-    )
-    .execute(&store.pool)
-    .await
-    .expect("Failed to update question");
+    //Wait for other writes, readers carry on meanwhile
+    let _writes = metrics::lock_writes(&store.writes).await;
+    updated_question.tags = store.normalize_tags(updated_question.tags);
 
-    //Update the question in the HashMap
-    if store.questions.contains_key(&question_id) {
-        store.questions.insert(question_id, updated_question);
-    }
+    let existing = store
+        .find_question(&question_id)
+        .await?
+        .ok_or(Error::QuestionNotFound)?;
+    policy::can_edit_question(&user, &existing)?;
+
+    //created_on and author_id are owned by the server, never take them from the body
+    updated_question.created_on = existing.created_on;
+    updated_question.author_id = existing.author_id;
+
+    //404 when it was deleted since the check above, e.g. by another instance,
+    //412 when it changed since the client read it
+    let stored = store
+        .update_question(&question_id, &updated_question, versions.as_deref())
+        .await?;
 
     //Return a response
-    Response::builder()
-        .status(StatusCode::OK)
-        .body("Question updated".to_string())
-        .unwrap()
+    Ok((
+        StatusCode::OK,
+        [(header::ETAG, precondition::etag(stored.version))],
+        "Question updated".to_string(),
+    ))
+}
+
+//A JSON Merge Patch (RFC 7386) of a question: fields left out keep their current
+//value and null removes one. Only tags can be removed
+#[derive(Deserialize, ToSchema, Debug)]
+#[serde(deny_unknown_fields)]
+struct QuestionPatch {
+    #[serde(default, deserialize_with = "extract::nullable")]
+    #[schema(value_type = String, required = false)]
+    title: Option<Option<String>>,
+    #[serde(default, deserialize_with = "extract::nullable")]
+    #[schema(value_type = String, required = false)]
+    content: Option<Option<String>>,
+    #[serde(default, deserialize_with = "extract::nullable")]
+    #[schema(value_type = Option<Vec<String>>, nullable)]
+    tags: Option<Option<Vec<String>>>,
+    //Owned by the server, accepted so a question read with GET, with or without
+    //include=answers, can be sent back as it is, but never applied.
+    //The /// comments are their description in the schema
+    /// Ignored, the id in the path is used
+    #[serde(rename = "id", default)]
+    #[schema(value_type = String, required = false, read_only)]
+    _id: IgnoredAny,
+    /// Ignored, send the version in If-Match instead
+    #[serde(rename = "version", default)]
+    #[schema(value_type = i64, required = false, read_only)]
+    _version: IgnoredAny,
+    /// Ignored, set by the server
+    #[serde(rename = "created_on", default)]
+    #[schema(value_type = String, format = DateTime, required = false, read_only)]
+    _created_on: IgnoredAny,
+    /// Ignored, set by the server
+    #[serde(rename = "author_id", default)]
+    #[schema(value_type = Option<String>, required = false, read_only)]
+    _author_id: IgnoredAny,
+    /// Ignored, answers are changed through their own routes
+    #[serde(rename = "answers", default)]
+    #[schema(value_type = Option<Vec<Answer>>, required = false, read_only)]
+    _answers: IgnoredAny,
+}
+
+impl QuestionPatch {
+    fn validate(&self) -> Result<(), Error> {
+        for (name, value) in [("title", &self.title), ("content", &self.content)] {
+            match value {
+                Some(None) => {
+                    return Err(Error::Validation(format!(
+                        "Question {name} cannot be removed"
+                    )))
+                }
+                Some(Some(value)) if value.trim().is_empty() => {
+                    return Err(Error::Validation(format!(
+                        "Question {name} must not be empty"
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+//Handler to change some fields of a question, see QuestionPatch. Only the fields in the
+//patch are written, so concurrent changes to the other fields are kept
+#[utoipa::path(
+    patch,
+    path = "/api/v1/questions/{id}",
+    tag = "questions",
+    params(
+        ("id" = String, Path, description = "Question id"),
+        ("If-Match" = Option<String>, Header, description = "ETags of the versions the change may be based on, comma-separated, or `*`. Required when require_if_match is on"),
+    ),
+    request_body(content = QuestionPatch, content_type = "application/merge-patch+json"),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The updated question", body = Question,
+            headers(("ETag" = String, description = "The new version, for If-Match"))),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Not the author or a moderator", body = ErrorBody),
+        (status = 404, description = "Question not found", body = ErrorBody),
+        (status = 412, description = "Changed since the If-Match version, this is the current question", body = Question),
+        (status = 422, description = "Unknown field, or a title or content that is empty or null", body = ErrorBody),
+        (status = 428, description = "If-Match is required", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id, question_id = %question_id))]
+async fn patch_question(
+    State(store): State<Arc<Store>>,
+    user: AuthUser,
+    Path(question_id): Path<String>,
+    IfMatch(versions): IfMatch,
+    JsonBody(mut patch): JsonBody<QuestionPatch>,
+) -> Result<impl IntoResponse, Error> {
+    patch.validate()?;
+
+    let _writes = metrics::lock_writes(&store.writes).await;
+
+    let existing = store
+        .find_question(&question_id)
+        .await?
+        .ok_or(Error::QuestionNotFound)?;
+    policy::can_edit_question(&user, &existing)?;
+
+    patch.tags = patch.tags.map(|tags| store.normalize_tags(tags));
+    let stored = store
+        .patch_question(&question_id, &patch, versions.as_deref())
+        .await?;
+    Ok((
+        [(header::ETAG, precondition::etag(stored.version))],
+        Json(stored),
+    ))
 }
 
 //Handler to delete a question
+#[utoipa::path(
+    delete,
+    path = "/api/v1/questions/{id}",
+    tag = "questions",
+    params(
+        ("id" = String, Path, description = "Question id"),
+        ("If-Match" = Option<String>, Header, description = "ETags of the versions the change may be based on, comma-separated, or `*`. Required when require_if_match is on"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Question and its answers deleted", body = Message),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Not the author or a moderator", body = ErrorBody),
+        (status = 404, description = "Question not found", body = ErrorBody),
+        (status = 412, description = "Changed since the If-Match version, this is the current question", body = Question),
+        (status = 428, description = "If-Match is required", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id, question_id = %question_id))]
 async fn delete_question(
+    user: AuthUser,
     Path(question_id): Path<String>,
-    State(store): State<Arc<Mutex<Store>>>,
-) -> impl IntoResponse {
-    let mut store = store.lock().await;
+    IfMatch(versions): IfMatch,
+    State(store): State<Arc<Store>>,
+) -> Result<impl IntoResponse, Error> {
+    let _writes = metrics::lock_writes(&store.writes).await;
 
-    // Execute the SQL delete query
-    sqlx::query!("DELETE FROM questions WHERE id = $1", question_id)
-        .execute(&store.pool)
-        .await
-        .expect("Failed to delete question");
-
-    //Check if the question exists and remove it
-    if store.questions.remove(&question_id).is_some() {
-        //Return success message
-        (
-            StatusCode::OK,
-            Json(json!({"message": "Question deleted successfully"})),
+    //Return an error if the question does not exist
+    let existing = store
+        .find_question(&question_id)
+        .await?
+        .ok_or(Error::QuestionNotFound)?;
+    policy::can_edit_question(&user, &existing)?;
+
+    //Answers are removed by the ON DELETE CASCADE
+    store
+        .delete_question(&question_id, versions.as_deref())
+        .await?;
+
+    //Return success message
+    Ok((
+        StatusCode::OK,
+        Message::new("Question deleted successfully"),
+    ))
+}
+
+//Handler to get all answers for a question
+#[utoipa::path(
+    get,
+    path = "/api/v1/questions/{id}/answers",
+    tag = "answers",
+    params(("id" = String, Path, description = "Question id")),
+    responses(
+        (status = 200, description = "Answers to the question, oldest first", body = [Answer]),
+        (status = 404, description = "Question not found", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(question_id = %question_id))]
+async fn question_answers(
+    Path(QuestionId(question_id)): Path<QuestionId>,
+    State(store): State<Arc<Store>>,
+) -> Result<Json<Vec<Answer>>, Error> {
+    if !store.question_exists(&question_id).await? {
+        return Err(Error::QuestionNotFound);
+    }
+
+    let answers = store.find_answers(&question_id).await?;
+    Ok(Json(answers))
+}
+
+// Handler to add an answer to a question
+#[utoipa::path(
+    post,
+    path = "/api/v1/questions/{id}/answers",
+    tag = "answers",
+    params(("id" = String, Path, description = "Question id")),
+    request_body = Answer,
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "Answer created, see the Location header", body = Answer),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Question not found", body = ErrorBody),
+        (status = 409, description = "An answer with this id already exists", body = ErrorBody),
+        (status = 422, description = "Invalid answer", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id, question_id = %question_id, answer_id))]
+async fn add_answer(
+    State(store): State<Arc<Store>>,
+    user: AuthUser,
+    Path(QuestionId(question_id)): Path<QuestionId>,
+    JsonBody(mut answer): JsonBody<Answer>,
+) -> Result<impl IntoResponse, Error> {
+    if answer.id.is_empty() {
+        answer.id = generate_id();
+    }
+    validate_id(&answer.id)?;
+    tracing::Span::current().record("answer_id", answer.id.as_str());
+    answer.validate()?;
+    answer.author_id = Some(user.id);
+    answer.created_on = now();
+
+    let _writes = metrics::lock_writes(&store.writes).await;
+
+    if !store.question_exists(&question_id).await? {
+        return Err(Error::QuestionNotFound);
+    }
+    answer.question_id = question_id;
+
+    store.insert_answer(&answer).await?;
+
+    let location = format!(
+        "/api/v1/questions/{}/answers/{}",
+        answer.question_id, answer.id
+    );
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, location)],
+        Json(answer),
+    ))
+}
+
+// Handler to update an existing answer
+#[utoipa::path(
+    put,
+    path = "/api/v1/questions/{id}/answers/{answer_id}",
+    tag = "answers",
+    params(
+        ("id" = String, Path, description = "Question id"),
+        ("answer_id" = String, Path, description = "Answer id"),
+    ),
+    request_body = Answer,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Answer updated", body = String, content_type = "text/plain"),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Not the author or a moderator", body = ErrorBody),
+        (status = 404, description = "Answer not found", body = ErrorBody),
+        (status = 422, description = "Invalid answer", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id, question_id = %question_id, answer_id = %answer_id))]
+async fn update_answer(
+    State(store): State<Arc<Store>>,
+    user: AuthUser,
+    Path((QuestionId(question_id), AnswerId(answer_id))): Path<(QuestionId, AnswerId)>,
+    JsonBody(mut updated_answer): JsonBody<Answer>,
+) -> Result<impl IntoResponse, Error> {
+    //The path is authoritative for both ids
+    updated_answer.id = answer_id.clone();
+    updated_answer.question_id = question_id.clone();
+    updated_answer.validate()?;
+
+    let _writes = metrics::lock_writes(&store.writes).await;
+
+    match store.repo.get_answer(&answer_id).await? {
+        Some(answer) if answer.question_id == question_id => {
+            policy::can_edit_answer(&user, &answer)?;
+            //author_id is owned by the server, never take it from the body
+            updated_answer.author_id = answer.author_id;
+        }
+        _ => return Err(Error::AnswerNotFound),
+    }
+
+    store.update_answer(&updated_answer).await?;
+
+    Ok((StatusCode::OK, "Answer updated".to_string()))
+}
+
+//Handler to delete an answer
+#[utoipa::path(
+    delete,
+    path = "/api/v1/questions/{id}/answers/{answer_id}",
+    tag = "answers",
+    params(
+        ("id" = String, Path, description = "Question id"),
+        ("answer_id" = String, Path, description = "Answer id"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Answer deleted", body = Message),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Not the author or a moderator", body = ErrorBody),
+        (status = 404, description = "Answer not found", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip_all, fields(user_id = %user.id, question_id = %question_id, answer_id = %answer_id))]
+async fn delete_answer(
+    user: AuthUser,
+    Path((QuestionId(question_id), AnswerId(answer_id))): Path<(QuestionId, AnswerId)>,
+    State(store): State<Arc<Store>>,
+) -> Result<impl IntoResponse, Error> {
+    let _writes = metrics::lock_writes(&store.writes).await;
+
+    match store.repo.get_answer(&answer_id).await? {
+        Some(answer) if answer.question_id == question_id => {
+            policy::can_edit_answer(&user, &answer)?;
+        }
+        _ => return Err(Error::AnswerNotFound),
+    }
+
+    store.delete_answer(&question_id, &answer_id).await?;
+
+    Ok((StatusCode::OK, Message::new("Answer deleted successfully")))
+}
+
+//The current API. Breaking changes go into a new version next to it
+fn api_v1() -> Router<AppState> {
+    Router::new()
+        .route("/auth/register", post(auth::register))
+        .route("/auth/login", post(auth::login))
+        .route("/admin/users/:username/role", put(auth::set_role))
+        .route("/questions", get(list_questions).post(add_question))
+        .route("/questions/search", get(search_questions))
+        .route(
+            "/questions/:id",
+            get(question_by_id)
+                .put(update_question)
+                .patch(patch_question)
+                .delete(delete_question),
         )
-    } else {
-        //Return an error if the question does not exist
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({"error": "Question not found"})),
+        .route(
+            "/questions/:id/answers",
+            get(question_answers).post(add_answer),
+        )
+        .route(
+            "/questions/:id/answers/:answer_id",
+            put(update_answer).delete(delete_answer),
         )
+        .route("/tags", get(tags))
+        .route("/tags/:name/questions", get(tag_questions))
+        .route("/admin/tags/rename", post(rename_tag))
+        .route("/admin/tags/merge", post(merge_tags))
+        .route(
+            "/admin/tags/synonyms",
+            get(tag_synonyms).post(add_tag_synonym),
+        )
+        .route("/admin/tags/synonyms/:synonym", delete(delete_tag_synonym))
+}
+
+//RFC 9745 Deprecation header: the date the unversioned paths were deprecated (2026-10-18)
+const LEGACY_DEPRECATION: &str = "@1792281600";
+//Where clients find the replacement routes
+const LEGACY_DEPRECATION_LINK: &str = "</docs>; rel=\"deprecation\"; type=\"text/html\"";
+
+//The paths from before /api/v1, kept working for the Yew app. Every response says it is deprecated
+fn legacy_routes() -> Router<AppState> {
+    Router::new()
+        .route("/auth/register", post(auth::register))
+        .route("/auth/login", post(auth::login))
+        .route("/admin/users/:username/role", put(auth::set_role))
+        .route("/questions", get(questions))
+        .route("/question", get(get_question))
+        .route("/questions/search", get(search_questions))
+        .route("/tags", get(tags))
+        .route("/tags/:name/questions", get(tag_questions))
+        .route("/admin/tags/rename", post(rename_tag))
+        .route("/admin/tags/merge", post(merge_tags))
+        .route(
+            "/admin/tags/synonyms",
+            get(tag_synonyms).post(add_tag_synonym),
+        )
+        .route("/admin/tags/synonyms/:synonym", delete(delete_tag_synonym))
+        .route("/add_question", post(add_question))
+        .route("/update_question/:id", put(update_question))
+        .route("/delete_questions/:id", delete(delete_question))
+        .route(
+            "/questions/:id/answers",
+            get(question_answers).post(add_answer),
+        )
+        .route(
+            "/questions/:id/answers/:answer_id",
+            put(update_answer).delete(delete_answer),
+        )
+        .layer(SetResponseHeaderLayer::overriding(
+            HeaderName::from_static("deprecation"),
+            HeaderValue::from_static(LEGACY_DEPRECATION),
+        ))
+        .layer(SetResponseHeaderLayer::appending(
+            header::LINK,
+            HeaderValue::from_static(LEGACY_DEPRECATION_LINK),
+        ))
+}
+
+//Startup failures end the process with a message instead of a panic
+fn exit_with(message: impl std::fmt::Display) -> ! {
+    tracing::error!("{message}");
+    std::process::exit(1);
+}
+
+//Any origin when the list contains "*", otherwise exactly the listed origins
+fn cors_origins(origins: &[String]) -> AllowOrigin {
+    if origins.iter().any(|origin| origin == "*") {
+        return AllowOrigin::any();
     }
+    //Config::validate already checked that every origin is a valid header value
+    AllowOrigin::list(
+        origins
+            .iter()
+            .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+    )
 }
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let pool = PgPool::connect(&database_url)
+
+    let cli = Cli::parse();
+    //Logging is configured by the config, so this one goes straight to stderr
+    let config = cli.load_config().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {e}");
+        std::process::exit(1);
+    });
+    telemetry::init(&config);
+
+    //--migrate-only lets deploys apply migrations without serving traffic
+    let mut database = config.database.clone();
+    database.run_migrations |= cli.migrate_only;
+
+    let repo = repository::open(&config.storage, &database)
         .await
-        .expect("Failed to connect to the database");
+        .unwrap_or_else(|e| exit_with(format!("Failed to open the storage backend: {e:?}")));
 
-    let store = Store::new(pool).await; // Store::new is an async function and should be awaited
-    let shared_store = Arc::new(Mutex::new(store)); // Wrap the store in Mutex, then in Arc
+    if cli.migrate_only {
+        tracing::info!("Migrations complete");
+        return;
+    }
+
+    //Subscribe before loading so nothing changed in between is missed
+    let changes = repo
+        .subscribe()
+        .await
+        .unwrap_or_else(|e| exit_with(format!("Failed to listen for storage changes: {e:?}")));
+    let store = Store::new(repo.clone(), &config.cache)
+        .await
+        .unwrap_or_else(|e| exit_with(format!("Failed to load tag synonyms from storage: {e:?}")));
+    //Shared by every request, the Store does its own locking
+    let shared_store = Arc::new(store);
+    if let Some(changes) = changes {
+        tokio::spawn(follow_changes(shared_store.clone(), changes));
+    }
+    let readiness = Arc::new(Readiness::default());
+    let app_state = AppState {
+        store: shared_store,
+        auth_keys: Arc::new(AuthKeys::from_config(&config.auth)),
+        config: Arc::new(config.clone()),
+        readiness: readiness.clone(),
+    };
+
+    let tracker = Arc::new(shutdown::RequestTracker::default());
+    let request_id_header = HeaderName::from_static(telemetry::REQUEST_ID_HEADER);
 
     let cors = CorsLayer::new()
-        .allow_origin(HeaderValue::from_static("http://127.0.0.1:9090"))
+        .allow_origin(cors_origins(&config.cors_origins))
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        //Browser clients read the version from it for If-Match
+        .expose_headers([header::ETAG]);
 
     let app = Router::new()
-        .route("/questions", get(questions))
-        .route("/question", get(get_question))
-        .route("/add_question", post(add_question))
-        .route("/update_question/:id", put(update_question))
-        .route("/delete_questions/:id", delete(delete_question))
+        .nest("/api/v1", api_v1())
+        .merge(legacy_routes())
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route("/metrics", get(metrics::metrics))
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/docs", get(openapi::explorer))
+        .route("/docs/:file", get(openapi::explorer_file))
+        .route_layer(middleware::from_fn(metrics::track_requests))
         .layer(cors)
-        .with_state(shared_store);
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3030));
-    println!("Listening on {}", addr);
-    Server::bind(&addr)
-        .serve(app.into_make_service())
+        .layer(middleware::from_fn_with_state(
+            tracker.clone(),
+            shutdown::track_requests,
+        ))
+        //Outermost first: assign an id, open the request span, echo the id in the response
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::new(
+                    request_id_header.clone(),
+                    telemetry::MakeRequestUlid,
+                ))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(telemetry::RequestSpan)
+                        .on_response(
+                            DefaultOnResponse::new()
+                                .level(Level::INFO)
+                                .latency_unit(LatencyUnit::Millis),
+                        ),
+                )
+                .layer(PropagateRequestIdLayer::new(request_id_header)),
+        )
+        .with_state(app_state);
+    let addr = config.listen_addr;
+    let server = Server::try_bind(&addr)
+        .unwrap_or_else(|e| exit_with(format!("Failed to listen on {addr}: {e}")));
+    tracing::info!(%addr, "Listening");
+
+    //After the signal the server stops accepting connections and waits for open requests
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let mut server = tokio::spawn(
+        server
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(async {
+                stop_rx.await.ok();
+            }),
+    );
+
+    tokio::select! {
+        result = &mut server => {
+            //The server only returns on its own when something went wrong
+            match result {
+                Ok(Err(e)) => exit_with(format!("Server error: {e}")),
+                _ => exit_with("Server stopped unexpectedly"),
+            }
+        }
+        _ = shutdown::signal() => {}
+    }
+
+    tracing::info!("Shutting down");
+    readiness.shutting_down();
+
+    //Keep serving while probes see the 503 and traffic moves elsewhere.
+    //A second signal skips the wait
+    let grace = Duration::from_secs(config.shutdown_readiness_grace_secs);
+    if !grace.is_zero() {
+        tracing::info!(?grace, "Reporting not ready before refusing connections");
+        tokio::select! {
+            _ = tokio::time::sleep(grace) => {}
+            _ = shutdown::signal() => tracing::info!("Second signal, refusing connections now"),
+        }
+    }
+    let in_flight = tracker.in_flight();
+    let completed_before = tracker.completed();
+    tracing::info!(
+        in_flight,
+        "Refusing new connections, waiting for in-flight requests"
+    );
+    stop_tx.send(()).ok();
+
+    let drain_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    match tokio::time::timeout(drain_timeout, &mut server).await {
+        Ok(Ok(Ok(()))) => {
+            tracing::info!(
+                drained = tracker.completed() - completed_before,
+                "Drained requests"
+            );
+        }
+        Ok(Ok(Err(e))) => tracing::error!(error = %e, "Server error while draining"),
+        Ok(Err(e)) => tracing::error!(error = %e, "Server task failed while draining"),
+        Err(_) => {
+            server.abort();
+            tracing::warn!(
+                timeout_secs = config.shutdown_timeout_secs,
+                drained = tracker.completed() - completed_before,
+                dropped = tracker.in_flight(),
+                "Gave up waiting for in-flight requests"
+            );
+        }
+    }
+
+    //Bounded as well, requests dropped above may still hold connections
+    if tokio::time::timeout(drain_timeout, repo.close())
         .await
-        .unwrap();
+        .is_err()
+    {
+        tracing::warn!("Timed out closing the storage backend");
+    }
+    tracing::info!("Shutdown complete");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_ids_are_valid() {
+        assert!(validate_id(&generate_id()).is_ok());
+        assert!(validate_id("what-is_rust.v2~draft").is_ok());
+        assert!(validate_id("...a").is_ok());
+    }
+
+    #[test]
+    fn ids_that_need_escaping_are_rejected() {
+        for id in ["", "a/b", "50%", "to be deleted"] {
+            assert!(
+                matches!(validate_id(id), Err(Error::Validation(_))),
+                "{id:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn dot_only_ids_are_rejected() {
+        for id in [".", "..", "..."] {
+            assert!(
+                matches!(validate_id(id), Err(Error::Validation(_))),
+                "{id:?} was accepted"
+            );
+        }
+    }
 }
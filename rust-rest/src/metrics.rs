@@ -0,0 +1,221 @@
+use crate::{error::Error, repository::Change, Store};
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
+use tokio::sync::{Mutex, MutexGuard};
+
+//Metrics are process wide, like the tracing subscriber, so every handler can
+//record into them without another piece of state to extract
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    store_write_wait: Histogram,
+    cache_changes: IntCounterVec,
+    cache_lookups: IntCounterVec,
+    cache_evictions: IntCounterVec,
+    cached_questions: IntGauge,
+    cached_answers: IntGauge,
+    db_connections: IntGauge,
+    db_idle_connections: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        //Lock waits are mostly far below the default buckets
+        let store_write_wait = Histogram::with_opts(
+            HistogramOpts::new(
+                "store_write_wait_seconds",
+                "Time writes spend waiting for other writes to the store",
+            )
+            .buckets(prometheus::exponential_buckets(0.00001, 4.0, 10).unwrap()),
+        )
+        .unwrap();
+        let cache_changes = IntCounterVec::new(
+            Opts::new(
+                "store_cache_changes_total",
+                "Changes from the storage backend applied to the cache, by kind",
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let cache_lookups = IntCounterVec::new(
+            Opts::new(
+                "store_cache_lookups_total",
+                "Store cache lookups by cache and result, hit or miss",
+            ),
+            &["cache", "result"],
+        )
+        .unwrap();
+        let cache_evictions = IntCounterVec::new(
+            Opts::new(
+                "store_cache_evictions_total",
+                "Entries dropped from the store cache by cache and reason, capacity or expired",
+            ),
+            &["cache", "reason"],
+        )
+        .unwrap();
+        let cached_questions =
+            IntGauge::new("store_cached_questions", "Questions in the store cache").unwrap();
+        let cached_answers = IntGauge::new(
+            "store_cached_answers",
+            "Questions whose answers are in the store cache",
+        )
+        .unwrap();
+        let db_connections = IntGauge::new(
+            "db_pool_connections",
+            "Open database connections, idle or in use",
+        )
+        .unwrap();
+        let db_idle_connections =
+            IntGauge::new("db_pool_idle_connections", "Idle database connections").unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry
+            .register(Box::new(request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(store_write_wait.clone()))
+            .unwrap();
+        registry.register(Box::new(cache_changes.clone())).unwrap();
+        registry.register(Box::new(cache_lookups.clone())).unwrap();
+        registry
+            .register(Box::new(cache_evictions.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(cached_questions.clone()))
+            .unwrap();
+        registry.register(Box::new(cached_answers.clone())).unwrap();
+        registry.register(Box::new(db_connections.clone())).unwrap();
+        registry
+            .register(Box::new(db_idle_connections.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            requests,
+            request_duration,
+            store_write_wait,
+            cache_changes,
+            cache_lookups,
+            cache_evictions,
+            cached_questions,
+            cached_answers,
+            db_connections,
+            db_idle_connections,
+        }
+    }
+}
+
+//Route layer middleware. Labels use the route pattern, e.g. /questions/:id/answers,
+//so ids do not blow up the number of series. Paths that match no route never get here
+pub async fn track_requests<B>(request: Request<B>, next: Next<B>) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let status = response.status().as_u16().to_string();
+    METRICS
+        .requests
+        .with_label_values(&[&method, &route, &status])
+        .inc();
+    METRICS
+        .request_duration
+        .with_label_values(&[&method, &route])
+        .observe(elapsed);
+    response
+}
+
+//Use instead of `store.writes.lock()` so time spent queueing behind other writes is recorded
+pub async fn lock_writes(writes: &Mutex<()>) -> MutexGuard<'_, ()> {
+    let start = Instant::now();
+    let guard = writes.lock().await;
+    METRICS
+        .store_write_wait
+        .observe(start.elapsed().as_secs_f64());
+    guard
+}
+
+pub fn record_cache_change(change: &Change) {
+    let kind = match change {
+        Change::Question(_) => "question",
+        Change::Answer(_) => "answer",
+        Change::TagSynonyms => "tag_synonyms",
+        Change::Resync => "resync",
+    };
+    METRICS.cache_changes.with_label_values(&[kind]).inc();
+}
+
+pub fn record_cache_lookup(cache: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    METRICS
+        .cache_lookups
+        .with_label_values(&[cache, result])
+        .inc();
+}
+
+pub fn record_cache_eviction(cache: &str, reason: &str) {
+    METRICS
+        .cache_evictions
+        .with_label_values(&[cache, reason])
+        .inc();
+}
+
+//Handler to export metrics in the Prometheus text format
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, description = "Prometheus text format", body = String, content_type = "text/plain"))
+)]
+pub async fn metrics(State(store): State<Arc<Store>>) -> Result<impl IntoResponse, Error> {
+    let (questions, answers) = store.cache_sizes();
+    METRICS.cached_questions.set(questions as i64);
+    METRICS.cached_answers.set(answers as i64);
+    if let Some(pool) = store.repo.pool_stats() {
+        METRICS.db_connections.set(i64::from(pool.size));
+        METRICS.db_idle_connections.set(pool.idle as i64);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder
+        .encode(&METRICS.registry.gather(), &mut body)
+        .map_err(|e| Error::Internal(format!("Failed to encode metrics: {e}")))?;
+    Ok((
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        body,
+    ))
+}
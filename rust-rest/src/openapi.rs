@@ -0,0 +1,176 @@
+use crate::{auth, error::ErrorBody, health, metrics, pagination, search, tags};
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::sync::OnceLock;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+//Every route and the types they exchange. A handler only shows up here once it
+//has a #[utoipa::path] attribute and is listed in paths
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Questions and answers API",
+        version = "1",
+        description = "Failed requests return `{ \"error\": \"...\" }` with a 4xx or 5xx status. \
+            The unversioned paths from before /api/v1 still work but are deprecated and not listed here."
+    ),
+    paths(
+        auth::register,
+        auth::login,
+        auth::set_role,
+        crate::list_questions,
+        crate::add_question,
+        crate::search_questions,
+        crate::question_by_id,
+        crate::update_question,
+        crate::patch_question,
+        crate::delete_question,
+        crate::question_answers,
+        crate::add_answer,
+        crate::update_answer,
+        crate::delete_answer,
+        crate::tags,
+        crate::tag_questions,
+        crate::rename_tag,
+        crate::merge_tags,
+        crate::tag_synonyms,
+        crate::add_tag_synonym,
+        crate::delete_tag_synonym,
+        health::healthz,
+        health::readyz,
+        metrics::metrics,
+    ),
+    components(schemas(
+        crate::Question,
+        crate::Answer,
+        crate::QuestionPatch,
+        crate::QuestionDetail,
+        crate::RenameTag,
+        crate::MergeTags,
+        crate::Retagged,
+        crate::SynonymAdded,
+        crate::Message,
+        pagination::QuestionPage,
+        search::SearchHit,
+        tags::TagCount,
+        tags::TagSynonym,
+        auth::Credentials,
+        auth::LoginResponse,
+        auth::UserProfile,
+        auth::Role,
+        auth::SetRole,
+        auth::RoleChange,
+        ErrorBody,
+    )),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "questions"),
+        (name = "answers"),
+        (name = "tags"),
+        (name = "auth", description = "Accounts and session tokens"),
+        (name = "admin", description = "Tag and user management, mostly for admins"),
+        (name = "operations", description = "Health checks and metrics"),
+    )
+)]
+struct ApiDoc;
+
+//The token from /auth/login, sent as `Authorization: Bearer <token>`
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+//Handler to get the OpenAPI document
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+//The Swagger UI release vendored by utoipa-swagger-ui-vendored, so /docs works offline
+//and serves exactly the files this build was tested with
+const SWAGGER_UI_DIST: &str = "swagger-ui-5.17.14/dist/";
+
+//Served under /docs/, with their content types
+const SWAGGER_UI_FILES: [(&str, &str); 2] = [
+    ("swagger-ui.css", "text/css; charset=utf-8"),
+    ("swagger-ui-bundle.js", "text/javascript; charset=utf-8"),
+];
+
+//Unpacked from the archive on first use and kept for the life of the process
+fn swagger_ui_files() -> &'static HashMap<&'static str, (&'static str, Vec<u8>)> {
+    static FILES: OnceLock<HashMap<&'static str, (&'static str, Vec<u8>)>> = OnceLock::new();
+    FILES.get_or_init(|| {
+        let archive = Cursor::new(utoipa_swagger_ui_vendored::SWAGGER_UI_VENDORED);
+        let mut archive = zip::ZipArchive::new(archive).expect("vendored Swagger UI is a zip");
+        SWAGGER_UI_FILES
+            .iter()
+            .map(|&(name, content_type)| {
+                let mut file = archive
+                    .by_name(&format!("{SWAGGER_UI_DIST}{name}"))
+                    .expect("vendored Swagger UI has its dist files");
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes)
+                    .expect("vendored Swagger UI unpacks");
+                (name, (content_type, bytes))
+            })
+            .collect()
+    })
+}
+
+const EXPLORER: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Questions and answers API</title>
+  <link rel="stylesheet" href="/docs/swagger-ui.css">
+</head>
+<body>
+  <div id="explorer"></div>
+  <script src="/docs/swagger-ui-bundle.js"></script>
+  <script>
+    window.onload = () => {
+      window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#explorer" });
+    };
+  </script>
+</body>
+</html>
+"##;
+
+//Handler for the interactive API explorer
+pub async fn explorer() -> Html<&'static str> {
+    Html(EXPLORER)
+}
+
+//Handler for the Swagger UI files the explorer page loads
+pub async fn explorer_file(Path(name): Path<String>) -> Response {
+    match swagger_ui_files().get(name.as_str()) {
+        Some((content_type, bytes)) => (
+            [
+                (header::CONTENT_TYPE, *content_type),
+                //The files only change with a new build
+                (header::CACHE_CONTROL, "public, max-age=86400"),
+            ],
+            bytes.as_slice(),
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{error::Error, Question};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, NaiveDateTime};
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+//Reads the optional `limit` query parameter
+pub fn parse_limit(params: &HashMap<String, String>) -> Result<usize, Error> {
+    match params.get("limit") {
+        Some(v) => v
+            .parse::<usize>()
+            .ok()
+            .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+            .ok_or(Error::ParseE(format!(
+                "Limit parameter must be between 1 and {MAX_LIMIT}"
+            ))),
+        None => Ok(DEFAULT_LIMIT),
+    }
+}
+
+//Position of the last question on a page. Clients only ever see the encoded form
+#[derive(Debug)]
+pub struct Cursor {
+    pub created_on: NaiveDateTime,
+    pub id: String,
+}
+
+impl Cursor {
+    pub fn after(question: &Question) -> Self {
+        Cursor {
+            created_on: question.created_on,
+            id: question.id.clone(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let raw = format!(
+            "{}:{}",
+            self.created_on.and_utc().timestamp_micros(),
+            self.id
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self, Error> {
+        let invalid = || Error::ParseE("Invalid cursor parameter".to_string());
+        let raw = URL_SAFE_NO_PAD.decode(encoded).map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        let (micros, id) = raw.split_once(':').ok_or_else(invalid)?;
+        let micros = micros.parse::<i64>().map_err(|_| invalid())?;
+        let created_on = DateTime::from_timestamp_micros(micros)
+            .ok_or_else(invalid)?
+            .naive_utc();
+        Ok(Cursor {
+            created_on,
+            id: id.to_string(),
+        })
+    }
+
+    fn is_before(&self, question: &Question) -> bool {
+        (self.created_on, self.id.as_str()) < sort_key(question)
+    }
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[aliases(QuestionPage = Page<Question>)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub total: usize,
+}
+
+//Questions are always listed oldest first, ties broken by id
+pub fn sort_key(question: &Question) -> (NaiveDateTime, &str) {
+    (question.created_on, question.id.as_str())
+}
+
+//Expects `questions` to already be sorted by sort_key
+pub fn paginate(questions: Vec<Question>, cursor: Option<&Cursor>, limit: usize) -> Page<Question> {
+    let total = questions.len();
+    let following = questions
+        .into_iter()
+        .filter(|question| cursor.is_none_or(|cursor| cursor.is_before(question)))
+        .take(limit.saturating_add(1))
+        .collect();
+    page(following, limit, total)
+}
+
+//Builds a page from the questions following the cursor, up to limit + 1 of them.
+//The one past the limit is only there to tell whether another page follows
+pub fn page(mut following: Vec<Question>, limit: usize, total: usize) -> Page<Question> {
+    let has_more = following.len() > limit;
+    following.truncate(limit);
+    let next_cursor = match following.last() {
+        Some(last) if has_more => Some(Cursor::after(last).encode()),
+        _ => None,
+    };
+
+    Page {
+        items: following,
+        next_cursor,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+
+    fn question(id: &str) -> Question {
+        serde_json::from_value(
+            serde_json::json!({ "id": id, "title": "Title", "content": "Content", "tags": null }),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn paginate_takes_everything_when_the_limit_is_unbounded() {
+        let questions = vec![question("1"), question("2")];
+        let page = paginate(questions, None, usize::MAX);
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.next_cursor, None);
+        assert_eq!(page.total, 2);
+    }
+
+    #[test]
+    fn paginate_points_past_the_last_question_when_more_follow() {
+        let questions = vec![question("1"), question("2"), question("3")];
+        let page = paginate(questions, None, 2);
+        assert_eq!(page.items.len(), 2);
+        let cursor = Cursor::decode(&page.next_cursor.unwrap()).unwrap();
+        assert_eq!(cursor.id, "2");
+    }
+
+    #[test]
+    fn cursors_survive_encoding() {
+        let created_on = DateTime::from_timestamp_micros(1_704_067_200_123_456)
+            .unwrap()
+            .naive_utc();
+        //Ids may contain the separator, only the first one splits
+        let cursor = Cursor {
+            created_on,
+            id: "a:b".to_string(),
+        };
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded.created_on, created_on);
+        assert_eq!(decoded.id, "a:b");
+    }
+
+    #[test]
+    fn ties_on_created_on_are_broken_by_id() {
+        //Every question has the same created_on
+        let questions = || vec![question("1"), question("2"), question("3")];
+        let first = paginate(questions(), None, 2);
+        let cursor = Cursor::decode(&first.next_cursor.unwrap()).unwrap();
+        let second = paginate(questions(), Some(&cursor), 2);
+        let ids: Vec<&str> = second.items.iter().map(|q| q.id.as_str()).collect();
+        assert_eq!(ids, ["3"]);
+        assert_eq!(second.next_cursor, None);
+    }
+
+    #[test]
+    fn malformed_cursors_are_bad_requests() {
+        let encode = |raw: &[u8]| URL_SAFE_NO_PAD.encode(raw);
+        for cursor in [
+            "not base64!".to_string(),
+            encode(b"no separator"),
+            encode(b"yesterday:1"),
+            encode(b"99999999999999999999:1"),
+            encode(&[0xff, b':', b'1']),
+        ] {
+            let error = Cursor::decode(&cursor).unwrap_err();
+            assert_eq!(
+                error.into_response().status(),
+                axum::http::StatusCode::BAD_REQUEST,
+                "{cursor} was accepted"
+            );
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{
+    auth::{AuthUser, Role},
+    error::Error,
+    Answer, Question,
+};
+
+//Who may do what. Handlers ask here instead of checking roles themselves,
+//so the rules live in one place and need nothing but plain values
+
+fn is_moderator(user: &AuthUser) -> bool {
+    matches!(user.role, Role::Moderator | Role::Admin)
+}
+
+//Authors may change their own posts, moderators and admins may change any.
+//Posts from before accounts existed have no author, so only moderators may touch them
+fn owns_or_moderates(user: &AuthUser, author_id: Option<&str>) -> bool {
+    author_id == Some(user.id.as_str()) || is_moderator(user)
+}
+
+pub fn can_edit_question(user: &AuthUser, question: &Question) -> Result<(), Error> {
+    if owns_or_moderates(user, question.author_id.as_deref()) {
+        return Ok(());
+    }
+    Err(Error::Forbidden(
+        "Only the author or a moderator may change this question".to_string(),
+    ))
+}
+
+pub fn can_edit_answer(user: &AuthUser, answer: &Answer) -> Result<(), Error> {
+    if owns_or_moderates(user, answer.author_id.as_deref()) {
+        return Ok(());
+    }
+    Err(Error::Forbidden(
+        "Only the author or a moderator may change this answer".to_string(),
+    ))
+}
+
+//Renames, merges and synonyms retag everyone's questions
+pub fn can_manage_tags(user: &AuthUser) -> Result<(), Error> {
+    if user.role == Role::Admin {
+        return Ok(());
+    }
+    Err(Error::Forbidden("Only admins may manage tags".to_string()))
+}
+
+pub fn can_manage_users(user: &AuthUser) -> Result<(), Error> {
+    if user.role == Role::Admin {
+        return Ok(());
+    }
+    Err(Error::Forbidden("Only admins may change roles".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::first_version;
+    use chrono::NaiveDateTime;
+
+    fn user(id: &str, role: Role) -> AuthUser {
+        AuthUser {
+            id: id.to_string(),
+            role,
+        }
+    }
+
+    fn question(author_id: Option<&str>) -> Question {
+        Question {
+            id: "q1".to_string(),
+            title: "Title".to_string(),
+            content: "Content".to_string(),
+            tags: None,
+            created_on: NaiveDateTime::default(),
+            author_id: author_id.map(str::to_string),
+            version: first_version(),
+        }
+    }
+
+    fn answer(author_id: Option<&str>) -> Answer {
+        Answer {
+            id: "a1".to_string(),
+            content: "Content".to_string(),
+            question_id: "q1".to_string(),
+            author_id: author_id.map(str::to_string),
+            created_on: NaiveDateTime::default(),
+        }
+    }
+
+    fn forbidden(result: Result<(), Error>) -> bool {
+        matches!(result, Err(Error::Forbidden(_)))
+    }
+
+    #[test]
+    fn authors_may_edit_their_own_posts() {
+        let author = user("alice", Role::User);
+        assert!(can_edit_question(&author, &question(Some("alice"))).is_ok());
+        assert!(can_edit_answer(&author, &answer(Some("alice"))).is_ok());
+    }
+
+    #[test]
+    fn other_users_may_not_edit_posts() {
+        let other = user("bob", Role::User);
+        assert!(forbidden(can_edit_question(
+            &other,
+            &question(Some("alice"))
+        )));
+        assert!(forbidden(can_edit_answer(&other, &answer(Some("alice")))));
+    }
+
+    #[test]
+    fn moderators_and_admins_may_edit_any_post() {
+        for role in [Role::Moderator, Role::Admin] {
+            let moderator = user("carol", role);
+            assert!(can_edit_question(&moderator, &question(Some("alice"))).is_ok());
+            assert!(can_edit_answer(&moderator, &answer(Some("alice"))).is_ok());
+        }
+    }
+
+    #[test]
+    fn posts_without_an_author_are_left_to_moderators() {
+        assert!(forbidden(can_edit_question(
+            &user("alice", Role::User),
+            &question(None)
+        )));
+        assert!(forbidden(can_edit_answer(
+            &user("alice", Role::User),
+            &answer(None)
+        )));
+        assert!(can_edit_question(&user("carol", Role::Moderator), &question(None)).is_ok());
+        assert!(can_edit_answer(&user("carol", Role::Moderator), &answer(None)).is_ok());
+    }
+
+    #[test]
+    fn only_admins_manage_tags_and_users() {
+        for role in [Role::User, Role::Moderator] {
+            assert!(forbidden(can_manage_tags(&user("carol", role))));
+            assert!(forbidden(can_manage_users(&user("carol", role))));
+        }
+        assert!(can_manage_tags(&user("dave", Role::Admin)).is_ok());
+        assert!(can_manage_users(&user("dave", Role::Admin)).is_ok());
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{config::Config, error::Error};
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header, request::Parts},
+};
+use std::sync::Arc;
+
+//Strong ETag for a question version, e.g. "3"
+pub fn etag(version: i64) -> String {
+    format!("\"{version}\"")
+}
+
+//The versions of the question a PUT, PATCH or DELETE may be based on, from If-Match.
+//None when any version will do: the header is `*`, or it was left out and
+//Config::require_if_match is off. The write is refused when the question is at
+//none of them, so a list with nothing in it is always refused
+pub struct IfMatch(pub Option<Vec<i64>>);
+
+//Only strong ETags handed out by etag() can match. Weak ones never match under the
+//strong comparison If-Match uses, and neither does anything else, so None
+fn version(tag: &str) -> Option<i64> {
+    tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok()
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfMatch
+where
+    Arc<Config>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let mut values = parts.headers.get_all(header::IF_MATCH).iter().peekable();
+        if values.peek().is_none() {
+            if Arc::<Config>::from_ref(state).require_if_match {
+                return Err(Error::PreconditionRequired);
+            }
+            return Ok(IfMatch(None));
+        }
+
+        //A list may be split over several If-Match headers
+        let tags: Vec<&str> = values
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        if tags.contains(&"*") {
+            return Ok(IfMatch(None));
+        }
+        Ok(IfMatch(Some(
+            tags.into_iter().filter_map(version).collect(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    async fn if_match(values: &[&str]) -> Option<Vec<i64>> {
+        let mut request = Request::builder();
+        for value in values {
+            request = request.header(header::IF_MATCH, *value);
+        }
+        let (mut parts, ()) = request.body(()).unwrap().into_parts();
+        let config = Arc::new(Config::default());
+        let Ok(IfMatch(versions)) = IfMatch::from_request_parts(&mut parts, &config).await else {
+            panic!("If-Match was refused");
+        };
+        versions
+    }
+
+    #[tokio::test]
+    async fn lists_may_name_several_versions() {
+        assert_eq!(if_match(&["\"1\", \"3\""]).await, Some(vec![1, 3]));
+        assert_eq!(if_match(&["\"1\"", "\"3\""]).await, Some(vec![1, 3]));
+        assert_eq!(if_match(&["\"1\", *"]).await, None);
+        assert_eq!(if_match(&[]).await, None);
+    }
+
+    #[tokio::test]
+    async fn lists_of_weak_tags_match_nothing() {
+        assert_eq!(if_match(&["W/\"3\""]).await, Some(vec![]));
+        assert_eq!(if_match(&["W/\"3\", \"4\""]).await, Some(vec![4]));
+    }
+
+    #[test]
+    fn strong_tags_match_their_version() {
+        assert_eq!(version(&etag(3)), Some(3));
+        assert_eq!(version("\"12\""), Some(12));
+    }
+
+    #[test]
+    fn weak_and_malformed_tags_never_match() {
+        assert_eq!(version("W/\"3\""), None);
+        assert_eq!(version("3"), None);
+        assert_eq!(version("\"three\""), None);
+    }
+}
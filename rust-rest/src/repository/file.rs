@@ -0,0 +1,277 @@
+use super::{
+    AnswerRepository, ChangeFeed, Diagnostics, Lifecycle, MemoryRepository, QuestionRepository,
+    TagRepository, UserRepository,
+};
+use crate::{
+    auth::{Role, User},
+    error::Error,
+    pagination::{Cursor, Page},
+    search::SearchHit,
+    tags::{TagCount, TagMatch, TagSynonym},
+    Answer, Question, QuestionPatch,
+};
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+//Serves from memory and rewrites the JSON files after every change.
+//The files use the same `{ "key": { ...question } }` layout as questions.json,
+//tag synonyms are stored as `{ "synonym": "canonical" }`
+pub struct FileRepository {
+    memory: MemoryRepository,
+    questions_path: PathBuf,
+    answers_path: PathBuf,
+    tag_synonyms_path: PathBuf,
+    users_path: PathBuf,
+}
+
+impl FileRepository {
+    pub async fn open(
+        questions_path: impl Into<PathBuf>,
+        answers_path: impl Into<PathBuf>,
+        tag_synonyms_path: impl Into<PathBuf>,
+        users_path: impl Into<PathBuf>,
+    ) -> Result<Self, Error> {
+        let questions_path = questions_path.into();
+        let answers_path = answers_path.into();
+        let tag_synonyms_path = tag_synonyms_path.into();
+        let users_path = users_path.into();
+
+        //Re-key by id, the keys in the checked in file are only labels
+        let questions = read_map::<Question>(&questions_path)
+            .await?
+            .into_values()
+            .map(|question| (question.id.clone(), question))
+            .collect();
+        let answers = read_map::<Answer>(&answers_path)
+            .await?
+            .into_values()
+            .map(|answer| (answer.id.clone(), answer))
+            .collect();
+        let tag_synonyms = read_map::<String>(&tag_synonyms_path).await?;
+        let users = read_map::<User>(&users_path).await?;
+
+        Ok(FileRepository {
+            memory: MemoryRepository::with_data(questions, answers, tag_synonyms, users),
+            questions_path,
+            answers_path,
+            tag_synonyms_path,
+            users_path,
+        })
+    }
+
+    async fn save_questions(&self) -> Result<(), Error> {
+        write_map(&self.questions_path, &self.memory.questions_snapshot()).await
+    }
+
+    async fn save_answers(&self) -> Result<(), Error> {
+        write_map(&self.answers_path, &self.memory.answers_snapshot()).await
+    }
+
+    async fn save_tag_synonyms(&self) -> Result<(), Error> {
+        write_map(
+            &self.tag_synonyms_path,
+            &self.memory.tag_synonyms_snapshot(),
+        )
+        .await
+    }
+
+    async fn save_users(&self) -> Result<(), Error> {
+        write_map(&self.users_path, &self.memory.users_snapshot()).await
+    }
+}
+
+//A missing file is treated as an empty store
+async fn read_map<T: DeserializeOwned>(path: &Path) -> Result<HashMap<String, T>, Error> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes).map_err(std::io::Error::from)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+//Writes a temporary file next to the original and renames it over the original, so a crash
+//midway leaves either the old or the new file, never a truncated one
+async fn write_map<T: Serialize>(path: &Path, map: &HashMap<String, T>) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(map).map_err(std::io::Error::from)?;
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut file = tokio::fs::File::create(&temp_path).await?;
+    file.write_all(json.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&temp_path, path).await?;
+
+    //The rename itself is only durable once the directory is synced
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        tokio::fs::File::open(dir).await?.sync_all().await?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl QuestionRepository for FileRepository {
+    async fn get_question(&self, id: &str) -> Result<Option<Question>, Error> {
+        self.memory.get_question(id).await
+    }
+
+    async fn list_questions(&self) -> Result<Vec<Question>, Error> {
+        self.memory.list_questions().await
+    }
+
+    async fn questions_page(
+        &self,
+        tags: &[String],
+        tag_match: TagMatch,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<Page<Question>, Error> {
+        self.memory
+            .questions_page(tags, tag_match, cursor, limit)
+            .await
+    }
+
+    async fn questions_range(&self, offset: usize, limit: usize) -> Result<Vec<Question>, Error> {
+        self.memory.questions_range(offset, limit).await
+    }
+
+    async fn insert_question(&self, question: &Question) -> Result<(), Error> {
+        self.memory.insert_question(question).await?;
+        self.save_questions().await
+    }
+
+    async fn update_question(
+        &self,
+        id: &str,
+        question: &Question,
+        versions: Option<&[i64]>,
+    ) -> Result<Option<Question>, Error> {
+        let stored = self.memory.update_question(id, question, versions).await?;
+        self.save_questions().await?;
+        Ok(stored)
+    }
+
+    async fn patch_question(
+        &self,
+        id: &str,
+        patch: &QuestionPatch,
+        versions: Option<&[i64]>,
+    ) -> Result<Option<Question>, Error> {
+        let stored = self.memory.patch_question(id, patch, versions).await?;
+        self.save_questions().await?;
+        Ok(stored)
+    }
+
+    async fn delete_question(&self, id: &str, versions: Option<&[i64]>) -> Result<bool, Error> {
+        let deleted = self.memory.delete_question(id, versions).await?;
+        self.save_questions().await?;
+        self.save_answers().await?;
+        Ok(deleted)
+    }
+
+    async fn search_questions(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, Error> {
+        self.memory.search_questions(query, limit).await
+    }
+
+    async fn questions_with_tags(
+        &self,
+        tags: &[String],
+        tag_match: TagMatch,
+    ) -> Result<Vec<Question>, Error> {
+        self.memory.questions_with_tags(tags, tag_match).await
+    }
+
+    async fn tag_counts(&self) -> Result<Vec<TagCount>, Error> {
+        self.memory.tag_counts().await
+    }
+}
+
+#[async_trait]
+impl AnswerRepository for FileRepository {
+    async fn get_answer(&self, id: &str) -> Result<Option<Answer>, Error> {
+        self.memory.get_answer(id).await
+    }
+
+    async fn answers_for_question(&self, question_id: &str) -> Result<Vec<Answer>, Error> {
+        self.memory.answers_for_question(question_id).await
+    }
+
+    async fn insert_answer(&self, answer: &Answer) -> Result<(), Error> {
+        self.memory.insert_answer(answer).await?;
+        self.save_answers().await
+    }
+
+    async fn update_answer(&self, id: &str, answer: &Answer) -> Result<Option<Answer>, Error> {
+        let stored = self.memory.update_answer(id, answer).await?;
+        self.save_answers().await?;
+        Ok(stored)
+    }
+
+    async fn delete_answer(&self, question_id: &str, id: &str) -> Result<bool, Error> {
+        let deleted = self.memory.delete_answer(question_id, id).await?;
+        self.save_answers().await?;
+        Ok(deleted)
+    }
+}
+
+#[async_trait]
+impl TagRepository for FileRepository {
+    async fn merge_tags(&self, from: &[String], into: &str) -> Result<u64, Error> {
+        let updated = self.memory.merge_tags(from, into).await?;
+        self.save_tag_synonyms().await?;
+        self.save_questions().await?;
+        Ok(updated)
+    }
+
+    async fn list_tag_synonyms(&self) -> Result<Vec<TagSynonym>, Error> {
+        self.memory.list_tag_synonyms().await
+    }
+
+    async fn insert_tag_synonym(&self, synonym: &TagSynonym) -> Result<u64, Error> {
+        let updated = self.memory.insert_tag_synonym(synonym).await?;
+        self.save_tag_synonyms().await?;
+        self.save_questions().await?;
+        Ok(updated)
+    }
+
+    async fn delete_tag_synonym(&self, synonym: &str) -> Result<bool, Error> {
+        let deleted = self.memory.delete_tag_synonym(synonym).await?;
+        self.save_tag_synonyms().await?;
+        Ok(deleted)
+    }
+}
+
+#[async_trait]
+impl UserRepository for FileRepository {
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, Error> {
+        self.memory.get_user_by_username(username).await
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), Error> {
+        self.memory.insert_user(user).await?;
+        self.save_users().await
+    }
+
+    async fn set_user_role(&self, username: &str, role: Role) -> Result<bool, Error> {
+        let updated = self.memory.set_user_role(username, role).await?;
+        self.save_users().await?;
+        Ok(updated)
+    }
+}
+
+//Every change is already written out when it happens
+impl Lifecycle for FileRepository {}
+
+impl Diagnostics for FileRepository {}
+
+//The files are only read at startup, so edits by others are not picked up anyway
+impl ChangeFeed for FileRepository {}
@@ -0,0 +1,382 @@
+use super::{
+    AnswerRepository, ChangeFeed, Diagnostics, Lifecycle, QuestionRepository, TagRepository,
+    UserRepository,
+};
+use crate::{
+    auth::{Role, User},
+    error::Error,
+    pagination::{self, Cursor, Page},
+    search,
+    search::SearchHit,
+    tags,
+    tags::{TagCount, TagMatch, TagSynonym},
+    Answer, Question, QuestionPatch,
+};
+use async_trait::async_trait;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::RwLock;
+
+//Keeps everything in process memory, nothing survives a restart
+#[derive(Default)]
+pub struct MemoryRepository {
+    questions: RwLock<HashMap<String, Question>>,
+    answers: RwLock<HashMap<String, Answer>>,
+    tag_synonyms: RwLock<HashMap<String, String>>,
+    users: RwLock<HashMap<String, User>>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_data(
+        questions: HashMap<String, Question>,
+        answers: HashMap<String, Answer>,
+        tag_synonyms: HashMap<String, String>,
+        users: HashMap<String, User>,
+    ) -> Self {
+        MemoryRepository {
+            questions: RwLock::new(questions),
+            answers: RwLock::new(answers),
+            tag_synonyms: RwLock::new(tag_synonyms),
+            users: RwLock::new(users),
+        }
+    }
+
+    pub fn questions_snapshot(&self) -> HashMap<String, Question> {
+        self.questions.read().unwrap().clone()
+    }
+
+    pub fn answers_snapshot(&self) -> HashMap<String, Answer> {
+        self.answers.read().unwrap().clone()
+    }
+
+    pub fn tag_synonyms_snapshot(&self) -> HashMap<String, String> {
+        self.tag_synonyms.read().unwrap().clone()
+    }
+
+    pub fn users_snapshot(&self) -> HashMap<String, User> {
+        self.users.read().unwrap().clone()
+    }
+}
+
+//The question with `id`, unless it is at none of `versions`
+fn at_version<'a>(
+    questions: &'a mut HashMap<String, Question>,
+    id: &str,
+    versions: Option<&[i64]>,
+) -> Option<&'a mut Question> {
+    questions
+        .get_mut(id)
+        .filter(|question| versions.is_none_or(|versions| versions.contains(&question.version)))
+}
+
+#[async_trait]
+impl QuestionRepository for MemoryRepository {
+    async fn get_question(&self, id: &str) -> Result<Option<Question>, Error> {
+        Ok(self.questions.read().unwrap().get(id).cloned())
+    }
+
+    async fn list_questions(&self) -> Result<Vec<Question>, Error> {
+        let mut questions: Vec<Question> =
+            self.questions.read().unwrap().values().cloned().collect();
+        questions.sort_by(|a, b| pagination::sort_key(a).cmp(&pagination::sort_key(b)));
+        Ok(questions)
+    }
+
+    async fn questions_page(
+        &self,
+        tags: &[String],
+        tag_match: TagMatch,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<Page<Question>, Error> {
+        let questions = if tags.is_empty() {
+            self.list_questions().await?
+        } else {
+            self.questions_with_tags(tags, tag_match).await?
+        };
+        Ok(pagination::paginate(questions, cursor, limit))
+    }
+
+    async fn questions_range(&self, offset: usize, limit: usize) -> Result<Vec<Question>, Error> {
+        let questions = self.list_questions().await?;
+        Ok(questions.into_iter().skip(offset).take(limit).collect())
+    }
+
+    async fn insert_question(&self, question: &Question) -> Result<(), Error> {
+        match self.questions.write().unwrap().entry(question.id.clone()) {
+            Entry::Occupied(_) => Err(Error::Conflict(
+                "A record with this id already exists".to_string(),
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(question.clone());
+                Ok(())
+            }
+        }
+    }
+
+    async fn update_question(
+        &self,
+        id: &str,
+        question: &Question,
+        versions: Option<&[i64]>,
+    ) -> Result<Option<Question>, Error> {
+        //Mirror the UPDATE statement: a missing row is left alone
+        let mut questions = self.questions.write().unwrap();
+        let Some(existing) = at_version(&mut questions, id, versions) else {
+            return Ok(None);
+        };
+        existing.title = question.title.clone();
+        existing.content = question.content.clone();
+        existing.tags = question.tags.clone();
+        existing.version += 1;
+        Ok(Some(existing.clone()))
+    }
+
+    async fn patch_question(
+        &self,
+        id: &str,
+        patch: &QuestionPatch,
+        versions: Option<&[i64]>,
+    ) -> Result<Option<Question>, Error> {
+        let mut questions = self.questions.write().unwrap();
+        let Some(existing) = at_version(&mut questions, id, versions) else {
+            return Ok(None);
+        };
+        if let Some(Some(title)) = &patch.title {
+            existing.title = title.clone();
+        }
+        if let Some(Some(content)) = &patch.content {
+            existing.content = content.clone();
+        }
+        if let Some(tags) = &patch.tags {
+            existing.tags = tags.clone();
+        }
+        existing.version += 1;
+        Ok(Some(existing.clone()))
+    }
+
+    async fn delete_question(&self, id: &str, versions: Option<&[i64]>) -> Result<bool, Error> {
+        {
+            let mut questions = self.questions.write().unwrap();
+            if at_version(&mut questions, id, versions).is_none() {
+                return Ok(false);
+            }
+            questions.remove(id);
+        }
+        self.answers
+            .write()
+            .unwrap()
+            .retain(|_, answer| answer.question_id != id);
+        Ok(true)
+    }
+
+    async fn search_questions(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, Error> {
+        let questions = self.questions.read().unwrap();
+        Ok(search::search(questions.values(), query, limit))
+    }
+
+    async fn questions_with_tags(
+        &self,
+        tags: &[String],
+        tag_match: TagMatch,
+    ) -> Result<Vec<Question>, Error> {
+        let mut questions: Vec<Question> = self
+            .questions
+            .read()
+            .unwrap()
+            .values()
+            .filter(|question| {
+                let question_tags = question.tags.as_deref().unwrap_or_default();
+                tag_match.matches(question_tags, tags)
+            })
+            .cloned()
+            .collect();
+        questions.sort_by(|a, b| pagination::sort_key(a).cmp(&pagination::sort_key(b)));
+        Ok(questions)
+    }
+
+    async fn tag_counts(&self) -> Result<Vec<TagCount>, Error> {
+        let mut counts: BTreeMap<String, i64> = BTreeMap::new();
+        for question in self.questions.read().unwrap().values() {
+            //A tag repeated on one question only counts once
+            let unique: BTreeSet<&String> = question.tags.iter().flatten().collect();
+            for tag in unique {
+                *counts.entry(tag.clone()).or_default() += 1;
+            }
+        }
+        Ok(counts
+            .into_iter()
+            .map(|(name, count)| TagCount { name, count })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl AnswerRepository for MemoryRepository {
+    async fn get_answer(&self, id: &str) -> Result<Option<Answer>, Error> {
+        Ok(self.answers.read().unwrap().get(id).cloned())
+    }
+
+    async fn answers_for_question(&self, question_id: &str) -> Result<Vec<Answer>, Error> {
+        let mut answers: Vec<Answer> = self
+            .answers
+            .read()
+            .unwrap()
+            .values()
+            .filter(|answer| answer.question_id == question_id)
+            .cloned()
+            .collect();
+        //Same order as the postgres backend, ids are not in creation order
+        answers.sort_by(|a, b| (a.created_on, &a.id).cmp(&(b.created_on, &b.id)));
+        Ok(answers)
+    }
+
+    async fn insert_answer(&self, answer: &Answer) -> Result<(), Error> {
+        //Mirror the foreign key on answers.question_id
+        if !self
+            .questions
+            .read()
+            .unwrap()
+            .contains_key(&answer.question_id)
+        {
+            return Err(Error::QuestionNotFound);
+        }
+        match self.answers.write().unwrap().entry(answer.id.clone()) {
+            Entry::Occupied(_) => Err(Error::Conflict(
+                "A record with this id already exists".to_string(),
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(answer.clone());
+                Ok(())
+            }
+        }
+    }
+
+    async fn update_answer(&self, id: &str, answer: &Answer) -> Result<Option<Answer>, Error> {
+        let mut answers = self.answers.write().unwrap();
+        match answers.get_mut(id) {
+            Some(existing) if existing.question_id == answer.question_id => {
+                existing.content = answer.content.clone();
+                Ok(Some(existing.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn delete_answer(&self, question_id: &str, id: &str) -> Result<bool, Error> {
+        let mut answers = self.answers.write().unwrap();
+        match answers.get(id) {
+            Some(existing) if existing.question_id == question_id => {
+                answers.remove(id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl TagRepository for MemoryRepository {
+    async fn merge_tags(&self, from: &[String], into: &str) -> Result<u64, Error> {
+        tags::merge_synonyms(&mut self.tag_synonyms.write().unwrap(), from, into);
+        let mut updated = 0;
+        for question in self.questions.write().unwrap().values_mut() {
+            if let Some(question_tags) = &mut question.tags {
+                if question_tags.iter().any(|tag| from.contains(tag)) {
+                    *question_tags = tags::merge(question_tags, from, into);
+                    //Mirror the trigger that bumps the version on every update
+                    question.version += 1;
+                    updated += 1;
+                }
+            }
+        }
+        Ok(updated)
+    }
+
+    async fn list_tag_synonyms(&self) -> Result<Vec<TagSynonym>, Error> {
+        let mut synonyms: Vec<TagSynonym> = self
+            .tag_synonyms
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(synonym, canonical)| TagSynonym {
+                synonym: synonym.clone(),
+                canonical: canonical.clone(),
+            })
+            .collect();
+        synonyms.sort_by(|a, b| a.synonym.cmp(&b.synonym));
+        Ok(synonyms)
+    }
+
+    async fn insert_tag_synonym(&self, synonym: &TagSynonym) -> Result<u64, Error> {
+        match self
+            .tag_synonyms
+            .write()
+            .unwrap()
+            .entry(synonym.synonym.clone())
+        {
+            Entry::Occupied(_) => {
+                return Err(Error::Conflict(
+                    "A record with this id already exists".to_string(),
+                ))
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(synonym.canonical.clone());
+            }
+        }
+        self.merge_tags(std::slice::from_ref(&synonym.synonym), &synonym.canonical)
+            .await
+    }
+
+    async fn delete_tag_synonym(&self, synonym: &str) -> Result<bool, Error> {
+        Ok(self.tag_synonyms.write().unwrap().remove(synonym).is_some())
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, Error> {
+        Ok(self
+            .users
+            .read()
+            .unwrap()
+            .values()
+            .find(|user| user.username == username)
+            .cloned())
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), Error> {
+        let mut users = self.users.write().unwrap();
+        //Mirror the primary key and the unique username constraint
+        if users.contains_key(&user.id) || users.values().any(|u| u.username == user.username) {
+            return Err(Error::Conflict(
+                "A record with this id already exists".to_string(),
+            ));
+        }
+        users.insert(user.id.clone(), user.clone());
+        Ok(())
+    }
+
+    async fn set_user_role(&self, username: &str, role: Role) -> Result<bool, Error> {
+        let mut users = self.users.write().unwrap();
+        match users.values_mut().find(|user| user.username == username) {
+            Some(user) => {
+                user.role = role;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+//Nothing to release
+impl Lifecycle for MemoryRepository {}
+
+impl Diagnostics for MemoryRepository {}
+
+//Only this process can change it
+impl ChangeFeed for MemoryRepository {}
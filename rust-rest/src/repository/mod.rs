@@ -0,0 +1,211 @@
+use crate::{
+    auth::{Role, User},
+    config::{Backend, DatabaseConfig, StorageConfig},
+    error::Error,
+    pagination::{Cursor, Page},
+    search::SearchHit,
+    tags::{TagCount, TagMatch, TagSynonym},
+    Answer, Question, QuestionPatch,
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+mod file;
+mod memory;
+mod postgres;
+
+pub use file::FileRepository;
+pub use memory::MemoryRepository;
+pub use postgres::PgRepository;
+
+#[async_trait]
+pub trait QuestionRepository: Send + Sync {
+    async fn get_question(&self, id: &str) -> Result<Option<Question>, Error>;
+    //Every question, in pagination::sort_key order
+    async fn list_questions(&self) -> Result<Vec<Question>, Error>;
+    //Up to `limit` questions following `cursor` in pagination::sort_key order, only those
+    //carrying any or all of `tags` when there are any. `total` counts every page
+    async fn questions_page(
+        &self,
+        tags: &[String],
+        tag_match: TagMatch,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<Page<Question>, Error>;
+    //Up to `limit` questions after skipping the first `offset` in pagination::sort_key order.
+    //Only for the start/end parameters of the legacy routes, new clients page with cursors
+    async fn questions_range(&self, offset: usize, limit: usize) -> Result<Vec<Question>, Error>;
+    //Fails with Error::Conflict when the id is taken
+    async fn insert_question(&self, question: &Question) -> Result<(), Error>;
+    //The writes below only go ahead when the question is at `version`, or at any version
+    //when it is None, and bump the version. They report no such question when it is not
+
+    //Changes title, content and tags, only when the question is at one of `versions`
+    //if given. Returns the question as stored, None when there was no such question
+    async fn update_question(
+        &self,
+        id: &str,
+        question: &Question,
+        versions: Option<&[i64]>,
+    ) -> Result<Option<Question>, Error>;
+    //Applies the patch in a single step, so concurrent changes to other fields are kept.
+    //Returns the question as stored, None when there was no such question
+    async fn patch_question(
+        &self,
+        id: &str,
+        patch: &QuestionPatch,
+        versions: Option<&[i64]>,
+    ) -> Result<Option<Question>, Error>;
+    //Deleting a question also deletes its answers. Returns false when there was no such question
+    async fn delete_question(&self, id: &str, versions: Option<&[i64]>) -> Result<bool, Error>;
+    //Full-text search over title, content and tags, best match first
+    async fn search_questions(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, Error>;
+    //Questions carrying any or all of `tags`, in pagination::sort_key order
+    async fn questions_with_tags(
+        &self,
+        tags: &[String],
+        tag_match: TagMatch,
+    ) -> Result<Vec<Question>, Error>;
+    //Every tag with the number of questions using it, sorted by name
+    async fn tag_counts(&self) -> Result<Vec<TagCount>, Error>;
+}
+
+#[async_trait]
+pub trait AnswerRepository: Send + Sync {
+    async fn get_answer(&self, id: &str) -> Result<Option<Answer>, Error>;
+    //Answers to one question, oldest first
+    async fn answers_for_question(&self, question_id: &str) -> Result<Vec<Answer>, Error>;
+    //Fails with Error::QuestionNotFound when the question does not exist
+    async fn insert_answer(&self, answer: &Answer) -> Result<(), Error>;
+    //Changes the content of the answer with `id` to `answer.question_id`. Returns the answer
+    //as stored, None when that question has no such answer
+    async fn update_answer(&self, id: &str, answer: &Answer) -> Result<Option<Answer>, Error>;
+    //Returns false when the question has no such answer
+    async fn delete_answer(&self, question_id: &str, id: &str) -> Result<bool, Error>;
+}
+
+#[async_trait]
+pub trait TagRepository: Send + Sync {
+    //Retags every question carrying one of `from` with `into` in a single step, and
+    //makes synonyms of `from` synonyms of `into`. Returns the number of questions changed
+    async fn merge_tags(&self, from: &[String], into: &str) -> Result<u64, Error>;
+    async fn list_tag_synonyms(&self) -> Result<Vec<TagSynonym>, Error>;
+    //Also merges the synonym into the canonical tag on existing questions,
+    //returns the number of questions changed
+    async fn insert_tag_synonym(&self, synonym: &TagSynonym) -> Result<u64, Error>;
+    //Returns false when there was no such synonym
+    async fn delete_tag_synonym(&self, synonym: &str) -> Result<bool, Error>;
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, Error>;
+    //Fails with Error::Conflict when the id or username is taken
+    async fn insert_user(&self, user: &User) -> Result<(), Error>;
+    //Returns false when there was no such user
+    async fn set_user_role(&self, username: &str, role: Role) -> Result<bool, Error>;
+}
+
+#[async_trait]
+pub trait Lifecycle: Send + Sync {
+    //Called once on shutdown after the last request finished
+    async fn close(&self) {}
+}
+
+pub struct PoolStats {
+    //Open connections, idle or in use
+    pub size: u32,
+    pub idle: usize,
+}
+
+//What the backend reports about itself for monitoring
+#[async_trait]
+pub trait Diagnostics: Send + Sync {
+    //None for backends without a connection pool
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+
+    //Fails when the backend cannot serve queries right now
+    async fn ping(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    //False while migrations shipped with this build are missing. Backends without a schema have none
+    async fn migrations_applied(&self) -> Result<bool, Error> {
+        Ok(true)
+    }
+}
+
+//A row that changed in the backend, possibly written by another instance
+#[derive(Debug)]
+pub enum Change {
+    Question(String),
+    //An answer to the question with this id
+    Answer(String),
+    //Any of the tag synonyms
+    TagSynonyms,
+    //Changes may have been missed, everything cached has to be reloaded
+    Resync,
+}
+
+#[async_trait]
+pub trait ChangeFeed: Send + Sync {
+    //Changes made by anyone, including this instance. None when the backend
+    //is not shared, so nobody else can change it
+    async fn subscribe(&self) -> Result<Option<mpsc::Receiver<Change>>, Error> {
+        Ok(None)
+    }
+}
+
+//Everything the Store needs from a storage backend
+pub trait Repository:
+    QuestionRepository
+    + AnswerRepository
+    + TagRepository
+    + UserRepository
+    + Lifecycle
+    + Diagnostics
+    + ChangeFeed
+{
+}
+
+impl<T> Repository for T where
+    T: QuestionRepository
+        + AnswerRepository
+        + TagRepository
+        + UserRepository
+        + Lifecycle
+        + Diagnostics
+        + ChangeFeed
+{
+}
+
+//Opens the storage backend picked in the config.
+//Migrations only apply to postgres, the other backends have no schema
+pub async fn open(
+    storage: &StorageConfig,
+    database: &DatabaseConfig,
+) -> Result<Arc<dyn Repository>, Error> {
+    let repo: Arc<dyn Repository> = match storage.backend {
+        Backend::Postgres => {
+            let repo = PgRepository::connect(database).await?;
+            if database.run_migrations {
+                repo.migrate().await?;
+            }
+            Arc::new(repo)
+        }
+        Backend::Memory => Arc::new(MemoryRepository::new()),
+        Backend::File => Arc::new(
+            FileRepository::open(
+                &storage.questions_file,
+                &storage.answers_file,
+                &storage.tag_synonyms_file,
+                &storage.users_file,
+            )
+            .await?,
+        ),
+    };
+    Ok(repo)
+}
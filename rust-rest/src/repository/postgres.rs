@@ -0,0 +1,658 @@
+use super::{
+    AnswerRepository, Change, ChangeFeed, Diagnostics, Lifecycle, PoolStats, QuestionRepository,
+    TagRepository, UserRepository,
+};
+use crate::{
+    auth::{Role, User},
+    config::DatabaseConfig,
+    error::Error,
+    pagination::{self, Cursor, Page},
+    search::{self, SearchHit, START_MARKER, STOP_MARKER},
+    tags::{TagCount, TagMatch, TagSynonym},
+    Answer, Question, QuestionPatch,
+};
+use async_trait::async_trait;
+use log::LevelFilter;
+use serde::Deserialize;
+use sqlx::{
+    postgres::{PgConnectOptions, PgListener, PgPoolOptions},
+    ConnectOptions, PgConnection, PgPool,
+};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+//Channel the notify_cache_change and notify_tag_synonyms_change triggers publish on
+const CHANGES_CHANNEL: &str = "cache_changes";
+
+//Payload of a cache_changes notification
+#[derive(Deserialize)]
+struct ChangeNotification {
+    table: String,
+    //Not set for tag_synonyms, every change reloads all of them
+    id: Option<String>,
+    //Only set for answers
+    question_id: Option<String>,
+}
+
+impl ChangeNotification {
+    fn into_change(self) -> Option<Change> {
+        match self.table.as_str() {
+            "questions" => self.id.map(Change::Question),
+            "answers" => self.question_id.map(Change::Answer),
+            "tag_synonyms" => Some(Change::TagSynonyms),
+            _ => None,
+        }
+    }
+}
+
+//The listener keeps one connection from the pool checked out for as long as it runs
+async fn listen(pool: &PgPool) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(CHANGES_CHANNEL).await?;
+    Ok(listener)
+}
+
+//Retries until listening again, waiting longer after every failure.
+//None once the pool is closed because the server is shutting down
+async fn relisten(pool: &PgPool) -> Option<PgListener> {
+    let mut delay = Duration::from_millis(100);
+    loop {
+        if pool.is_closed() {
+            return None;
+        }
+        match listen(pool).await {
+            Ok(listener) => return Some(listener),
+            Err(e) => {
+                tracing::warn!(error = %e, retry_in = ?delay, "Failed to listen for cache changes");
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(Duration::from_secs(30));
+            }
+        }
+    }
+}
+
+//Forwards notifications until the pool is closed or nobody receives them anymore.
+//Notifications sent while the connection was down are lost, so after listening
+//again it asks for a resync
+async fn forward_changes(pool: PgPool, mut listener: PgListener, changes: mpsc::Sender<Change>) {
+    loop {
+        let change = match listener.try_recv().await {
+            Ok(Some(notification)) => {
+                match serde_json::from_str::<ChangeNotification>(notification.payload())
+                    .ok()
+                    .and_then(ChangeNotification::into_change)
+                {
+                    Some(change) => change,
+                    None => {
+                        tracing::warn!(
+                            payload = notification.payload(),
+                            "Ignoring unexpected cache change notification"
+                        );
+                        continue;
+                    }
+                }
+            }
+            Err(sqlx::Error::PoolClosed) => return,
+            lost => {
+                if let Err(e) = lost {
+                    tracing::warn!(error = %e, "Cache change listener failed");
+                } else {
+                    tracing::warn!("Cache change listener lost its connection");
+                }
+                //Start over with a fresh connection rather than trusting the old one
+                drop(listener);
+                listener = match relisten(&pool).await {
+                    Some(listener) => listener,
+                    None => return,
+                };
+                tracing::info!("Listening for cache changes again");
+                Change::Resync
+            }
+        };
+        if changes.send(change).await.is_err() {
+            return;
+        }
+    }
+}
+
+pub struct PgRepository {
+    pool: PgPool,
+}
+
+impl PgRepository {
+    pub async fn connect(config: &DatabaseConfig) -> Result<Self, Error> {
+        //Config::validate makes sure the url is there for the postgres backend
+        let database_url = config.url.as_deref().unwrap_or_default();
+        let mut options = PgConnectOptions::from_str(database_url)?;
+        //Every statement is logged with its duration, slow ones loud enough to show by default
+        options
+            .log_statements(LevelFilter::Debug)
+            .log_slow_statements(
+                LevelFilter::Warn,
+                Duration::from_millis(config.slow_statement_ms),
+            );
+        let idle_timeout =
+            (config.idle_timeout_secs > 0).then(|| Duration::from_secs(config.idle_timeout_secs));
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+            .idle_timeout(idle_timeout)
+            .connect_with(options)
+            .await?;
+        Ok(PgRepository { pool })
+    }
+
+    //Shared by merge_tags and insert_tag_synonym, runs in their transaction.
+    //Synonyms of the merged tags are pointed at `into`, the same as tags::merge_synonyms
+    async fn merge_tags_with(
+        conn: &mut PgConnection,
+        from: &[String],
+        into: &str,
+    ) -> Result<u64, Error> {
+        sqlx::query!("DELETE FROM tag_synonyms WHERE synonym = $1", into)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query!(
+            "UPDATE tag_synonyms SET canonical = $2 WHERE canonical = ANY($1)",
+            from,
+            into
+        )
+        .execute(&mut *conn)
+        .await?;
+        let result = sqlx::query!(
+            "UPDATE questions SET tags = ARRAY(
+                SELECT tag FROM (
+                    SELECT CASE WHEN tag = ANY($1) THEN $2 ELSE tag END AS tag, MIN(ord) AS first
+                    FROM unnest(questions.tags) WITH ORDINALITY AS t(tag, ord)
+                    GROUP BY 1
+                ) merged
+                ORDER BY first
+            )
+            WHERE tags && $1",
+            from,
+            into
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    //Applies everything in migrations/ that has not been run yet
+    #[tracing::instrument(skip(self))]
+    pub async fn migrate(&self) -> Result<(), Error> {
+        sqlx::migrate!()
+            .run(&self.pool)
+            .await
+            .map_err(sqlx::Error::from)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl QuestionRepository for PgRepository {
+    #[tracing::instrument(skip(self))]
+    async fn get_question(&self, id: &str) -> Result<Option<Question>, Error> {
+        let question = sqlx::query_as!(
+            Question,
+            "SELECT id, title, content, tags, created_on, author_id, version FROM questions WHERE id = $1",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(question)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn list_questions(&self) -> Result<Vec<Question>, Error> {
+        let questions = sqlx::query_as!(
+            Question,
+            "SELECT id, title, content, tags, created_on, author_id, version FROM questions
+            ORDER BY created_on, id"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(questions)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn questions_page(
+        &self,
+        tags: &[String],
+        tag_match: TagMatch,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<Page<Question>, Error> {
+        //NULLs switch a condition off. Each call is planned with its parameters,
+        //so the ones that are switched off do not keep the indexes from being used
+        let tags = (!tags.is_empty()).then_some(tags);
+        let match_all = tag_match == TagMatch::All;
+        let (after_created_on, after_id) = match cursor {
+            Some(cursor) => (Some(cursor.created_on), Some(cursor.id.as_str())),
+            None => (None, None),
+        };
+        let following = sqlx::query_as!(
+            Question,
+            "SELECT id, title, content, tags, created_on, author_id, version FROM questions
+            WHERE ($1::text[] IS NULL OR CASE WHEN $2 THEN tags @> $1 ELSE tags && $1 END)
+            AND ($3::timestamp IS NULL OR (created_on, id) > ($3, $4::text))
+            ORDER BY created_on, id
+            LIMIT $5",
+            tags,
+            match_all,
+            after_created_on,
+            after_id,
+            i64::try_from(limit).unwrap_or(i64::MAX).saturating_add(1)
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let total = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM questions
+            WHERE ($1::text[] IS NULL OR CASE WHEN $2 THEN tags @> $1 ELSE tags && $1 END)"#,
+            tags,
+            match_all
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(pagination::page(following, limit, total as usize))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn questions_range(&self, offset: usize, limit: usize) -> Result<Vec<Question>, Error> {
+        let questions = sqlx::query_as!(
+            Question,
+            "SELECT id, title, content, tags, created_on, author_id, version FROM questions
+            ORDER BY created_on, id
+            OFFSET $1 LIMIT $2",
+            i64::try_from(offset).unwrap_or(i64::MAX),
+            i64::try_from(limit).unwrap_or(i64::MAX)
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(questions)
+    }
+
+    #[tracing::instrument(skip_all, fields(question_id = %question.id))]
+    async fn insert_question(&self, question: &Question) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO questions (id, title, content, tags, created_on, author_id, version)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            question.id,
+            question.title,
+            question.content,
+            question.tags.as_deref(),
+            question.created_on,
+            question.author_id,
+            question.version
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(question_id = %id))]
+    async fn update_question(
+        &self,
+        id: &str,
+        question: &Question,
+        versions: Option<&[i64]>,
+    ) -> Result<Option<Question>, Error> {
+        //questions_bump_version bumps the version
+        let stored = sqlx::query_as!(
+            Question,
+            "UPDATE questions SET title = $2, content = $3, tags = $4
+            WHERE id = $1 AND ($5::bigint[] IS NULL OR version = ANY($5))
+            RETURNING id, title, content, tags, created_on, author_id, version",
+            id,
+            question.title,
+            question.content,
+            question.tags.as_deref(),
+            versions
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(stored)
+    }
+
+    #[tracing::instrument(skip_all, fields(question_id = %id))]
+    async fn patch_question(
+        &self,
+        id: &str,
+        patch: &QuestionPatch,
+        versions: Option<&[i64]>,
+    ) -> Result<Option<Question>, Error> {
+        //NULL title or content keeps the column, tags are only set when $4 is true
+        let stored = sqlx::query_as!(
+            Question,
+            "UPDATE questions SET
+                title = COALESCE($2, title),
+                content = COALESCE($3, content),
+                tags = CASE WHEN $4 THEN $5 ELSE tags END
+            WHERE id = $1 AND ($6::bigint[] IS NULL OR version = ANY($6))
+            RETURNING id, title, content, tags, created_on, author_id, version",
+            id,
+            patch.title.as_ref().and_then(Option::as_deref),
+            patch.content.as_ref().and_then(Option::as_deref),
+            patch.tags.is_some(),
+            patch.tags.as_ref().and_then(Option::as_deref),
+            versions
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(stored)
+    }
+
+    #[tracing::instrument(skip_all, fields(question_id = %id))]
+    async fn delete_question(&self, id: &str, versions: Option<&[i64]>) -> Result<bool, Error> {
+        let result = sqlx::query!(
+            "DELETE FROM questions WHERE id = $1 AND ($2::bigint[] IS NULL OR version = ANY($2))",
+            id,
+            versions
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn search_questions(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, Error> {
+        let headline_options =
+            format!("StartSel={START_MARKER}, StopSel={STOP_MARKER}, MaxWords=20, MinWords=8");
+        let (from, to) = search::headline_translation();
+        let rows = sqlx::query!(
+            r#"SELECT id, title, content, tags, created_on, author_id, version,
+                ts_rank(search_document, query) AS "rank!",
+                ts_headline('english', translate(title || ' ' || content, $4, $5), query, $3)
+                    AS "snippet!"
+            FROM questions, websearch_to_tsquery('english', $1) AS query
+            WHERE search_document @@ query
+            ORDER BY 8 DESC, created_on, id
+            LIMIT $2"#,
+            query,
+            i64::try_from(limit).unwrap_or(i64::MAX),
+            headline_options,
+            from,
+            to
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let hits = rows
+            .into_iter()
+            .map(|row| SearchHit {
+                question: Question {
+                    id: row.id,
+                    title: row.title,
+                    content: row.content,
+                    tags: row.tags,
+                    created_on: row.created_on,
+                    author_id: row.author_id,
+                    version: row.version,
+                },
+                rank: row.rank,
+                snippet: search::mark_up(&row.snippet),
+            })
+            .collect();
+        Ok(hits)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn questions_with_tags(
+        &self,
+        tags: &[String],
+        tag_match: TagMatch,
+    ) -> Result<Vec<Question>, Error> {
+        //&& and @> can both use the GIN index on tags
+        let questions = match tag_match {
+            TagMatch::Any => {
+                sqlx::query_as!(
+                    Question,
+                    "SELECT id, title, content, tags, created_on, author_id, version FROM questions
+                    WHERE tags && $1 ORDER BY created_on, id",
+                    tags
+                )
+                .fetch_all(&self.pool)
+                .await?
+            }
+            TagMatch::All => {
+                sqlx::query_as!(
+                    Question,
+                    "SELECT id, title, content, tags, created_on, author_id, version FROM questions
+                    WHERE tags @> $1 ORDER BY created_on, id",
+                    tags
+                )
+                .fetch_all(&self.pool)
+                .await?
+            }
+        };
+        Ok(questions)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn tag_counts(&self) -> Result<Vec<TagCount>, Error> {
+        let counts = sqlx::query_as!(
+            TagCount,
+            r#"SELECT tag AS "name!", COUNT(DISTINCT id) AS "count!"
+            FROM questions, unnest(tags) AS tag
+            GROUP BY tag ORDER BY tag"#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(counts)
+    }
+}
+
+#[async_trait]
+impl AnswerRepository for PgRepository {
+    #[tracing::instrument(skip(self))]
+    async fn get_answer(&self, id: &str) -> Result<Option<Answer>, Error> {
+        let answer = sqlx::query_as!(
+            Answer,
+            "SELECT id, content, question_id, author_id, created_on FROM answers WHERE id = $1",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(answer)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn answers_for_question(&self, question_id: &str) -> Result<Vec<Answer>, Error> {
+        //Clients may pick ids, so they say nothing about when an answer was added
+        let answers = sqlx::query_as!(
+            Answer,
+            "SELECT id, content, question_id, author_id, created_on FROM answers
+            WHERE question_id = $1 ORDER BY created_on, id",
+            question_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(answers)
+    }
+
+    #[tracing::instrument(skip_all, fields(question_id = %answer.question_id, answer_id = %answer.id))]
+    async fn insert_answer(&self, answer: &Answer) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO answers (id, content, question_id, author_id, created_on)
+            VALUES ($1, $2, $3, $4, $5)",
+            answer.id,
+            answer.content,
+            answer.question_id,
+            answer.author_id,
+            answer.created_on
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(answer_id = %id))]
+    async fn update_answer(&self, id: &str, answer: &Answer) -> Result<Option<Answer>, Error> {
+        let stored = sqlx::query_as!(
+            Answer,
+            "UPDATE answers SET content = $3 WHERE id = $1 AND question_id = $2
+            RETURNING id, content, question_id, author_id, created_on",
+            id,
+            answer.question_id,
+            answer.content
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(stored)
+    }
+
+    #[tracing::instrument(skip_all, fields(question_id = %question_id, answer_id = %id))]
+    async fn delete_answer(&self, question_id: &str, id: &str) -> Result<bool, Error> {
+        let result = sqlx::query!(
+            "DELETE FROM answers WHERE id = $1 AND question_id = $2",
+            id,
+            question_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl TagRepository for PgRepository {
+    #[tracing::instrument(skip(self))]
+    async fn merge_tags(&self, from: &[String], into: &str) -> Result<u64, Error> {
+        //One transaction, so other requests never see a half merged tag
+        let mut tx = self.pool.begin().await?;
+        let updated = Self::merge_tags_with(&mut tx, from, into).await?;
+        tx.commit().await?;
+        Ok(updated)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn list_tag_synonyms(&self) -> Result<Vec<TagSynonym>, Error> {
+        let synonyms = sqlx::query_as!(
+            TagSynonym,
+            "SELECT synonym, canonical FROM tag_synonyms ORDER BY synonym"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(synonyms)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_tag_synonym(&self, synonym: &TagSynonym) -> Result<u64, Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            "INSERT INTO tag_synonyms (synonym, canonical) VALUES ($1, $2)",
+            synonym.synonym,
+            synonym.canonical
+        )
+        .execute(&mut tx)
+        .await?;
+        let updated = Self::merge_tags_with(
+            &mut tx,
+            std::slice::from_ref(&synonym.synonym),
+            &synonym.canonical,
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(updated)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_tag_synonym(&self, synonym: &str) -> Result<bool, Error> {
+        let result = sqlx::query!("DELETE FROM tag_synonyms WHERE synonym = $1", synonym)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgRepository {
+    #[tracing::instrument(skip(self))]
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, Error> {
+        let user = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, password_hash, created_on, role AS "role: Role"
+            FROM users WHERE username = $1"#,
+            username
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
+    #[tracing::instrument(skip_all, fields(username = %user.username))]
+    async fn insert_user(&self, user: &User) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO users (id, username, password_hash, created_on, role)
+            VALUES ($1, $2, $3, $4, $5)",
+            user.id,
+            user.username,
+            user.password_hash,
+            user.created_on,
+            user.role as Role
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn set_user_role(&self, username: &str, role: Role) -> Result<bool, Error> {
+        let result = sqlx::query!(
+            "UPDATE users SET role = $2 WHERE username = $1",
+            username,
+            role as Role
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl Lifecycle for PgRepository {
+    //Waits for checked out connections to be returned, then closes them all
+    #[tracing::instrument(skip(self))]
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+#[async_trait]
+impl ChangeFeed for PgRepository {
+    //Other instances and manual edits reach the same database, the notify_cache_change
+    //trigger reports every row they change
+    async fn subscribe(&self) -> Result<Option<mpsc::Receiver<Change>>, Error> {
+        let listener = listen(&self.pool).await?;
+        let (sender, receiver) = mpsc::channel(1024);
+        tokio::spawn(forward_changes(self.pool.clone(), listener, sender));
+        Ok(Some(receiver))
+    }
+}
+
+#[async_trait]
+impl Diagnostics for PgRepository {
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+        })
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn migrations_applied(&self) -> Result<bool, Error> {
+        //Not query! so the build does not depend on sqlx's bookkeeping table
+        let applied: Vec<i64> =
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(sqlx::migrate!()
+            .iter()
+            .all(|migration| applied.contains(&migration.version)))
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{pagination, Question};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+//Matches are wrapped in these. Everything else in a snippet is HTML escaped,
+//so clients can render it as HTML without running markup from questions
+pub const START_SEL: &str = "<mark>";
+pub const STOP_SEL: &str = "</mark>";
+
+//ts_headline marks matches with these private use characters instead of START_SEL and
+//STOP_SEL, which would be indistinguishable from markup in the question. They are
+//removed from the text first, then mark_up swaps them for the real markers
+pub const START_MARKER: char = '\u{E000}';
+pub const STOP_MARKER: char = '\u{E001}';
+//ts_headline drops anything between < and > as an HTML tag, so they stand in for them,
+//e.g. in Vec<String>. mark_up turns them back into escaped < and >
+pub const LT_MARKER: char = '\u{E002}';
+pub const GT_MARKER: char = '\u{E003}';
+
+//Arguments of the SQL translate() that prepares text for ts_headline: < and > become
+//LT_MARKER and GT_MARKER, and the markers already in the text are removed
+pub fn headline_translation() -> (String, String) {
+    (
+        format!("<>{LT_MARKER}{GT_MARKER}{START_MARKER}{STOP_MARKER}"),
+        format!("{LT_MARKER}{GT_MARKER}"),
+    )
+}
+
+//The default ts_rank weights for the A, B and C labels given to title, content and tags
+const TITLE_WEIGHT: f32 = 1.0;
+const CONTENT_WEIGHT: f32 = 0.4;
+const TAG_WEIGHT: f32 = 0.2;
+
+//Number of words shown around the first match in a snippet
+const SNIPPET_WORDS: usize = 12;
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct SearchHit {
+    pub question: Question,
+    pub rank: f32,
+    pub snippet: String,
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+//Lowercased search terms, matched as word prefixes so "async" finds "asynchronous"
+fn terms(query: &str) -> Vec<String> {
+    words(query).collect()
+}
+
+fn matches(word: &str, terms: &[String]) -> bool {
+    terms.iter().any(|term| word.starts_with(term.as_str()))
+}
+
+fn field_score(text: &str, term: &str) -> f32 {
+    let (hits, total) = words(text).fold((0, 0), |(hits, total), word| {
+        (hits + usize::from(word.starts_with(term)), total + 1)
+    });
+    if total == 0 {
+        return 0.0;
+    }
+    //Dampen long fields the way ts_rank does with its length normalization
+    hits as f32 / (1.0 + (total as f32).ln())
+}
+
+//None unless every term appears somewhere in the question, like websearch_to_tsquery
+fn score(question: &Question, terms: &[String]) -> Option<f32> {
+    let tags = question.tags.as_deref().unwrap_or_default().join(" ");
+    let mut rank = 0.0;
+    for term in terms {
+        let term_rank = TITLE_WEIGHT * field_score(&question.title, term)
+            + CONTENT_WEIGHT * field_score(&question.content, term)
+            + TAG_WEIGHT * field_score(&tags, term);
+        if term_rank == 0.0 {
+            return None;
+        }
+        rank += term_rank;
+    }
+    Some(rank)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+//Escapes a ts_headline result and turns START_MARKER and STOP_MARKER into START_SEL and STOP_SEL
+pub fn mark_up(headline: &str) -> String {
+    let headline = headline.replace(LT_MARKER, "<").replace(GT_MARKER, ">");
+    escape_html(&headline)
+        .replace(START_MARKER, START_SEL)
+        .replace(STOP_MARKER, STOP_SEL)
+}
+
+//A window of words around the first match with matches highlighted and the rest escaped
+fn snippet(question: &Question, terms: &[String]) -> String {
+    let text = format!("{} {}", question.title, question.content);
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let first_match = tokens
+        .iter()
+        .position(|token| words(token).any(|word| matches(&word, terms)))
+        .unwrap_or(0);
+    let start = first_match.saturating_sub(SNIPPET_WORDS / 3);
+
+    tokens
+        .iter()
+        .skip(start)
+        .take(SNIPPET_WORDS)
+        .map(|token| {
+            if words(token).any(|word| matches(&word, terms)) {
+                format!("{START_SEL}{}{STOP_SEL}", escape_html(token))
+            } else {
+                escape_html(token)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+//Scores every question in memory, best match first
+pub fn search<'a>(
+    questions: impl Iterator<Item = &'a Question>,
+    query: &str,
+    limit: usize,
+) -> Vec<SearchHit> {
+    let terms = terms(query);
+    if terms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut hits: Vec<SearchHit> = questions
+        .filter_map(|question| {
+            score(question, &terms).map(|rank| SearchHit {
+                question: question.clone(),
+                rank,
+                snippet: snippet(question, &terms),
+            })
+        })
+        .collect();
+    hits.sort_by(|a, b| {
+        b.rank
+            .total_cmp(&a.rank)
+            .then_with(|| pagination::sort_key(&a.question).cmp(&pagination::sort_key(&b.question)))
+    });
+    hits.truncate(limit);
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(title: &str, content: &str) -> Question {
+        serde_json::from_value(
+            serde_json::json!({ "title": title, "content": content, "tags": null }),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn snippet_escapes_markup_from_the_question() {
+        let question = question("<script>alert(1)</script> rust", "\"quoted\" & more");
+        let snippet = snippet(&question, &terms("rust"));
+        assert_eq!(
+            snippet,
+            "&lt;script&gt;alert(1)&lt;/script&gt; <mark>rust</mark> &quot;quoted&quot; &amp; more"
+        );
+    }
+
+    #[test]
+    fn mark_up_escapes_everything_but_the_markers() {
+        let headline = format!("<b>{START_MARKER}rust{STOP_MARKER}</b>");
+        assert_eq!(mark_up(&headline), "&lt;b&gt;<mark>rust</mark>&lt;/b&gt;");
+    }
+
+    #[test]
+    fn generic_types_survive_the_snippet() {
+        let question = question("Collect into Vec<String>", "with async iterators");
+        let snippet = snippet(&question, &terms("async"));
+        assert_eq!(
+            snippet,
+            "Collect into Vec&lt;String&gt; with <mark>async</mark> iterators"
+        );
+
+        //What ts_headline returns for the text translated by headline_translation
+        let headline = format!("Vec{LT_MARKER}{START_MARKER}String{STOP_MARKER}{GT_MARKER} with");
+        assert_eq!(mark_up(&headline), "Vec&lt;<mark>String</mark>&gt; with");
+    }
+
+    #[test]
+    fn headline_translation_maps_every_bracket_and_drops_the_markers() {
+        let (from, to) = headline_translation();
+        //translate() maps the n-th character of `from` to the n-th of `to`, the rest are removed
+        let mut from = from.chars();
+        assert_eq!(from.next(), Some('<'));
+        assert_eq!(from.next(), Some('>'));
+        assert_eq!(to, format!("{LT_MARKER}{GT_MARKER}"));
+        let removed: Vec<char> = from.collect();
+        for marker in [LT_MARKER, GT_MARKER, START_MARKER, STOP_MARKER] {
+            assert!(removed.contains(&marker));
+        }
+    }
+}
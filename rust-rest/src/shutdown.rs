@@ -0,0 +1,65 @@
+use axum::{extract::State, http::Request, middleware::Next, response::Response};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+//Counts requests so shutdown can report how many it waited for
+#[derive(Default)]
+pub struct RequestTracker {
+    in_flight: AtomicUsize,
+    completed: AtomicU64,
+}
+
+impl RequestTracker {
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn completed(&self) -> u64 {
+        self.completed.load(Ordering::SeqCst)
+    }
+}
+
+//Decrements on drop, so a request that is cancelled still stops counting as in flight
+struct InFlight(Arc<RequestTracker>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.0.completed.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+//Middleware that keeps the tracker up to date
+pub async fn track_requests<B>(
+    State(tracker): State<Arc<RequestTracker>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    tracker.in_flight.fetch_add(1, Ordering::SeqCst);
+    let _in_flight = InFlight(tracker);
+    next.run(request).await
+}
+
+//Resolves on Ctrl-C, or SIGTERM on unix (what docker stop and kubernetes send)
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl-C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
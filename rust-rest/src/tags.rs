@@ -0,0 +1,177 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct TagCount {
+    pub name: String,
+    pub count: i64,
+}
+
+//A question tagged with `synonym` is stored under `canonical` instead
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct TagSynonym {
+    pub synonym: String,
+    pub canonical: String,
+}
+
+//How a list of tags in a filter is combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagMatch {
+    Any,
+    All,
+}
+
+impl TagMatch {
+    pub fn parse(value: Option<&str>) -> Result<Self, Error> {
+        match value {
+            None | Some("any") => Ok(TagMatch::Any),
+            Some("all") => Ok(TagMatch::All),
+            Some(_) => Err(Error::ParseE(
+                "Match parameter must be any or all".to_string(),
+            )),
+        }
+    }
+
+    pub fn matches(self, question_tags: &[String], tags: &[String]) -> bool {
+        match self {
+            TagMatch::Any => tags.iter().any(|tag| question_tags.contains(tag)),
+            TagMatch::All => tags.iter().all(|tag| question_tags.contains(tag)),
+        }
+    }
+}
+
+//Trimmed and lowercased, so " Rust" and "rust" are the same tag
+pub fn normalize_name(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+//The tag a name is stored under once synonyms are applied
+pub fn canonical(tag: &str, synonyms: &HashMap<String, String>) -> String {
+    let tag = normalize_name(tag);
+    synonyms.get(&tag).cloned().unwrap_or(tag)
+}
+
+//Applied to every tag list before it is written: normalizes names, resolves
+//synonyms, drops blanks and duplicates while keeping the original order
+pub fn normalize(
+    tags: Option<Vec<String>>,
+    synonyms: &HashMap<String, String>,
+) -> Option<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags? {
+        let tag = canonical(&tag, synonyms);
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    Some(normalized)
+}
+
+//Synonyms of tags merged into `into` become synonyms of `into`, so they never bring
+//back a merged tag. A synonym named `into` would hide it, so it is dropped
+pub fn merge_synonyms(synonyms: &mut HashMap<String, String>, from: &[String], into: &str) {
+    synonyms.remove(into);
+    for canonical in synonyms.values_mut() {
+        if from.contains(canonical) {
+            *canonical = into.to_string();
+        }
+    }
+}
+
+//Replaces every tag in `from` with `into`, keeping the first position it appears at
+pub fn merge(tags: &[String], from: &[String], into: &str) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = if from.contains(tag) {
+            into.to_string()
+        } else {
+            tag.clone()
+        };
+        if !merged.contains(&tag) {
+            merged.push(tag);
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    fn synonyms(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(synonym, canonical)| (synonym.to_string(), canonical.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn names_are_trimmed_and_lowercased() {
+        assert_eq!(normalize_name("  Rust "), "rust");
+        assert_eq!(normalize_name("ASYNC"), "async");
+        assert_eq!(normalize_name("   "), "");
+    }
+
+    #[test]
+    fn normalize_drops_blanks_and_duplicates_in_order() {
+        let tags = strings(&["Rust", " async", "", "rust ", "ASYNC", "tokio"]);
+        assert_eq!(
+            normalize(Some(tags), &HashMap::new()),
+            Some(strings(&["rust", "async", "tokio"]))
+        );
+        assert_eq!(normalize(None, &HashMap::new()), None);
+    }
+
+    #[test]
+    fn normalize_resolves_synonyms() {
+        let synonyms = synonyms(&[("js", "javascript"), ("rs", "rust")]);
+        assert_eq!(canonical(" JS", &synonyms), "javascript");
+        assert_eq!(canonical("python", &synonyms), "python");
+        //A synonym and its canonical tag are one tag
+        let tags = strings(&["rs", "javascript", "Rust", "js"]);
+        assert_eq!(
+            normalize(Some(tags), &synonyms),
+            Some(strings(&["rust", "javascript"]))
+        );
+    }
+
+    #[test]
+    fn merge_keeps_the_first_position_of_the_merged_tag() {
+        let tags = strings(&["async", "tokio", "rust", "futures"]);
+        let from = strings(&["futures", "tokio"]);
+        assert_eq!(merge(&tags, &from, "async"), strings(&["async", "rust"]));
+        assert_eq!(
+            merge(&tags, &from, "runtime"),
+            strings(&["async", "runtime", "rust"])
+        );
+    }
+
+    #[test]
+    fn merging_a_tag_moves_its_synonyms_along() {
+        let mut synonyms = synonyms(&[
+            ("js", "javascript"),
+            ("es", "ecmascript"),
+            ("rs", "rust"),
+            ("web", "ecmascript"),
+        ]);
+        merge_synonyms(&mut synonyms, &strings(&["javascript"]), "ecmascript");
+        let expected = self::synonyms(&[
+            ("js", "ecmascript"),
+            ("es", "ecmascript"),
+            ("rs", "rust"),
+            ("web", "ecmascript"),
+        ]);
+        assert_eq!(synonyms, expected);
+
+        //A synonym with the name of the target would keep it from being used
+        let mut synonyms = self::synonyms(&[("ecmascript", "es6"), ("js", "javascript")]);
+        merge_synonyms(&mut synonyms, &strings(&["javascript"]), "ecmascript");
+        assert_eq!(synonyms, self::synonyms(&[("js", "ecmascript")]));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::config::{Config, LogFormat, LogLevel};
+use http::{HeaderValue, Request};
+use tower_http::request_id::{MakeRequestId, RequestId};
+use tower_http::trace::MakeSpan;
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
+use ulid::Ulid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+impl LogLevel {
+    fn directive(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+//Installs the global subscriber, which also picks up sqlx's statement logs.
+//RUST_LOG, when set, takes precedence over log_level so single modules can be
+//turned up, e.g. RUST_LOG=info,rust_rest::repository=debug,sqlx=debug
+pub fn init(config: &Config) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        //Postgres notices are mostly "already exists, skipping" from migrations
+        EnvFilter::new(format!(
+            "{},sqlx::postgres::notice=warn",
+            config.log_level.directive()
+        ))
+    });
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.log_format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(true).init(),
+    }
+}
+
+//Request ids look like every other id we hand out
+#[derive(Clone, Copy, Default)]
+pub struct MakeRequestUlid;
+
+impl MakeRequestId for MakeRequestUlid {
+    fn make_request_id<B>(&mut self, _request: &Request<B>) -> Option<RequestId> {
+        let id = HeaderValue::from_str(&Ulid::new().to_string()).ok()?;
+        Some(RequestId::new(id))
+    }
+}
+
+//The span every log line of a request is nested in. Runs after SetRequestId,
+//so the header holds either the caller's id or a generated one
+#[derive(Clone, Copy, Default)]
+pub struct RequestSpan;
+
+impl<B> MakeSpan<B> for RequestSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        tracing::info_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            request_id,
+        )
+    }
+}
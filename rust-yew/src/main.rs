@@ -19,7 +19,75 @@ fn app() -> Html {
     let update_question_id = use_state(String::new);
     let update_title = use_state(String::new);
     let update_content = use_state(String::new);
-    let update_tags = use_state(Vec::new);
+    // Kept as typed, so "rust," stays in the field until the next tag is entered. Split on submit
+    let update_tags = use_state(String::new);
+    // Version of the question loaded into the update form, sent as If-Match so edits made meanwhile are not overwritten
+    let update_version = use_state(|| None::<i64>);
+
+    let username = use_state(String::new);
+    let password = use_state(String::new);
+    let token = use_state(String::new);
+
+    // Callback for updating username state on input event
+    let on_username_input = {
+        let username = username.clone();
+        Callback::from(move |e: InputEvent| {
+            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+            username.set(input.value());
+        })
+    };
+
+    // Callback for updating password state on input event
+    let on_password_input = {
+        let password = password.clone();
+        Callback::from(move |e: InputEvent| {
+            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+            password.set(input.value());
+        })
+    };
+
+    // Callback for handling form submission to log in, keeps the token for later requests
+    let on_login_submit = {
+        let username = username.clone();
+        let password = password.clone();
+        let token = token.clone();
+        Callback::from(move |e: FocusEvent| {
+            e.prevent_default();
+            let data = serde_json::json!({
+                "username": (*username).clone(),
+                "password": (*password).clone()
+            });
+            let token = token.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let url = "http://127.0.0.1:3030/auth/login";
+                match Request::post(url)
+                    .header("Content-Type", "application/json")
+                    .body(data.to_string())
+                    .send()
+                    .await {
+                    Ok(response) => {
+                        if response.ok() {
+                            match response.json::<Login>().await {
+                                Ok(login) => {
+                                    console::log_1(&"Logged in successfully".into());
+                                    token.set(login.token);
+                                }
+                                Err(err) => {
+                                    console::error_1(&"Failed to parse JSON".into());
+                                    console::error_1(&format!("{:?}", err).into());
+                                }
+                            }
+                        } else {
+                            console::error_1(&"Invalid username or password".into());
+                        }
+                    }
+                    Err(err) => {
+                        console::error_1(&format!("Error sending request: {:?}", err).into());
+                    }
+                }
+            });
+        })
+    };
     
     // Callback for updating question_id state on input event
     let on_id_add = {
@@ -53,8 +121,7 @@ fn app() -> Html {
         let tags = tags.clone();
         Callback::from(move |e: InputEvent| {
             let input: web_sys::HtmlInputElement = e.target_unchecked_into();
-            let tags_list = input.value().split(',').map(String::from).collect();
-            tags.set(tags_list);
+            tags.set(parse_tags(&input.value()));
         })
     };
 
@@ -64,18 +131,24 @@ fn app() -> Html {
         let title = title.clone();
         let content = content.clone();
         let tags = tags.clone();
+        let token = token.clone();
         Callback::from(move |e: FocusEvent| {
             e.prevent_default();
-            let data = serde_json::json!({
-                "id": (*question_id).clone(),
+            let authorization = format!("Bearer {}", *token);
+            let mut data = serde_json::json!({
                 "title": (*title).clone(),
                 "content": (*content).clone(),
                 "tags": (*tags).clone()
             });
+            // Leave the ID out so the server generates one
+            if !question_id.is_empty() {
+                data["id"] = serde_json::json!((*question_id).clone());
+            }
             wasm_bindgen_futures::spawn_local(async move {
                 let url = "http://127.0.0.1:3030/add_question";
                 match Request::post(url)
                     .header("Content-Type", "application/json")
+                    .header("Authorization", &authorization)
                     .body(data.to_string())
                     .send()
                     .await {
@@ -108,12 +181,17 @@ fn app() -> Html {
     // Callback for form submission to delete a question
     let on_delete_submit = {
         let delete_id = delete_id.clone();
+        let token = token.clone();
         Callback::from(move |e: FocusEvent| {
             e.prevent_default(); // Prevent the form from actually submitting
             let id = delete_id.to_string();
+            let authorization = format!("Bearer {}", *token);
             wasm_bindgen_futures::spawn_local(async move {
                 let url = format!("http://127.0.0.1:3030/delete_questions/{}", encode(&id));
-                match Request::delete(&url).send().await {
+                match Request::delete(&url)
+                    .header("Authorization", &authorization)
+                    .send()
+                    .await {
                     Ok(response) => {
                         if response.ok() {
                             console::log_1(&"Question deleted successfully".into());
@@ -130,12 +208,30 @@ fn app() -> Html {
         })
     };
 
-    // Callback for updating question_id state on input event
+    // Callback for updating question_id state on input event, the version of a loaded question no longer applies
     let on_id_update = {
         let update_question_id = update_question_id.clone();
+        let update_version = update_version.clone();
         Callback::from(move |e: InputEvent| {
             let input: web_sys::HtmlInputElement = e.target_unchecked_into();
             update_question_id.set(input.value());
+            update_version.set(None);
+        })
+    };
+
+    // Callback for filling the update form with a question and remembering its version
+    let on_edit = {
+        let update_question_id = update_question_id.clone();
+        let update_title = update_title.clone();
+        let update_content = update_content.clone();
+        let update_tags = update_tags.clone();
+        let update_version = update_version.clone();
+        Callback::from(move |question: Question| {
+            update_question_id.set(question.id);
+            update_title.set(question.title);
+            update_content.set(question.content);
+            update_tags.set(question.tags.unwrap_or_default().join(", "));
+            update_version.set(Some(question.version));
         })
     };
 
@@ -162,8 +258,7 @@ fn app() -> Html {
         let update_tags = update_tags.clone();
         Callback::from(move |e: InputEvent| {
             let input: web_sys::HtmlInputElement = e.target_unchecked_into();
-            let tags_list = input.value().split(',').map(String::from).collect();
-            update_tags.set(tags_list);
+            update_tags.set(input.value());
         })
     };
 
@@ -173,26 +268,51 @@ fn app() -> Html {
         let update_title = update_title.clone();
         let update_content = update_content.clone();
         let update_tags = update_tags.clone();
+        let update_version = update_version.clone();
+        let on_edit = on_edit.clone();
+        let token = token.clone();
         Callback::from(move |e: FocusEvent| {
             e.prevent_default();
+            let authorization = format!("Bearer {}", *token);
+            let if_match = (*update_version).map(|version| format!("\"{}\"", version));
             let data = serde_json::json!({
                 "id": (*update_question_id).clone(),
                 "title": (*update_title).clone(),
                 "content": (*update_content).clone(),
-                "tags": (*update_tags).clone()
+                "tags": parse_tags(&update_tags)
             });
             wasm_bindgen_futures::spawn_local({
                 let update_question_id = update_question_id.clone();
+                let update_version = update_version.clone();
+                let on_edit = on_edit.clone();
                 async move {
                     let url = format!("http://127.0.0.1:3030/update_question/{}", (*update_question_id).clone());
-                    match Request::put(&url)
+                    let mut request = Request::put(&url)
                         .header("Content-Type", "application/json")
+                        .header("Authorization", &authorization);
+                    if let Some(if_match) = &if_match {
+                        request = request.header("If-Match", if_match);
+                    }
+                    match request
                         .body(data.to_string())
                         .send()
                         .await {
                         Ok(response) => {
                             if response.ok() {
                                 console::log_1(&"Question updated successfully".into());
+                                // The next update is based on the version just written
+                                let version = response
+                                    .headers()
+                                    .get("etag")
+                                    .and_then(|etag| etag.trim_matches('"').parse().ok());
+                                update_version.set(version);
+                            } else if response.status() == 412 {
+                                // Someone else changed it meanwhile, show their version instead of overwriting it
+                                console::error_1(&"The question was changed by someone else, the form now shows the current version. Apply your changes again and resubmit".into());
+                                match response.json::<Question>().await {
+                                    Ok(current) => on_edit.emit(current),
+                                    Err(err) => console::error_1(&format!("{:?}", err).into()),
+                                }
                             } else {
                                 let status = response.status();
                                 let status_text = response.status_text();
@@ -310,12 +430,21 @@ let on_click_paginate = {
         <div>
             <marquee>{ "WORK IN PROGRESS" }</marquee>
             <h1 style="text-align:center;">{ "Marvin's Rust Web App" }</h1>
+
+            <div style="text-align: center; margin-bottom: 20px;">
+                <form onsubmit={on_login_submit}>
+                    <input type="text" placeholder="Username" oninput={on_username_input} />
+                    <input type="password" placeholder="Password" oninput={on_password_input} />
+                    <button type="submit">{ "Log In" }</button>
+                </form>
+                <div>{ if token.is_empty() { "Not logged in" } else { "Logged in" } }</div>
+            </div>
             
             <div style="display: flex; justify-content: center; margin-bottom: 20px;">
                 <div style="margin: 0 20px; padding: 10px;">
                     <h3 style="text-align: center;">{ "ADD QUESTION" }</h3>
                     <form onsubmit={on_add_submit}>
-                        <input type="text" placeholder="ID" oninput={on_id_add} />
+                        <input type="text" placeholder="ID (optional)" oninput={on_id_add} />
                         <input type="text" placeholder="Title" oninput={on_title_add} />
                         <input type="text" placeholder="Content" oninput={on_content_add} />
                         <input type="text" placeholder="Tags (comma-separated)" oninput={on_tags_add} />
@@ -325,10 +454,10 @@ let on_click_paginate = {
                 <div style="margin: 0 20px; padding: 10px;">
                     <h3 style="text-align: center;">{ "UPDATE QUESTION" }</h3>
                     <form onsubmit={on_update_submit}>
-                        <input type="text" placeholder="Update ID" oninput={on_id_update} />
-                        <input type="text" placeholder="Update Title" oninput={on_title_update} />
-                        <input type="text" placeholder="Update Content" oninput={on_content_update} />
-                        <input type="text" placeholder="Update Tags (comma-separated)" oninput={on_tags_update} />
+                        <input type="text" placeholder="Update ID" value={(*update_question_id).clone()} oninput={on_id_update} />
+                        <input type="text" placeholder="Update Title" value={(*update_title).clone()} oninput={on_title_update} />
+                        <input type="text" placeholder="Update Content" value={(*update_content).clone()} oninput={on_content_update} />
+                        <input type="text" placeholder="Update Tags (comma-separated)" value={(*update_tags).clone()} oninput={on_tags_update} />
                         <button type="submit">{ "Update Question" }</button>
                     </form>
                 </div>
@@ -364,6 +493,11 @@ let on_click_paginate = {
                             Some(tags) => tags.join(", "),
                             None => "No tags".to_string(),
                         }) }</div>
+                        <button onclick={
+                            let on_edit = on_edit.clone();
+                            let q = q.clone();
+                            Callback::from(move |_| on_edit.emit(q.clone()))
+                        }>{ "Edit" }</button>
                     </li> }) }
                 </ul>
             </div>
@@ -375,12 +509,26 @@ let on_click_paginate = {
 }
 
 
+// Splits a comma-separated tags field, dropping blank entries
+fn parse_tags(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
 #[derive(Clone, Debug, PartialEq, Properties, Deserialize)]
 struct Question {
     id: String,
     title: String,
     content: String,
     tags: Option<Vec<String>>,
+    version: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Login {
+    token: String,
 }
 
 fn main() {